@@ -1,7 +1,10 @@
 use clap::{arg, Command};
 use std::error::Error;
 use log::LevelFilter;
-use whiley::command::{build,clean,init,install,run};
+use whiley::command::{build,cache,clean,init,install,parse_age,publish,run,toolchain,vendor,CacheCommand,ToolchainCommand};
+use whiley::fetch;
+use whiley::fetch::Mode;
+use whiley::jvm;
 use whiley::{init_logging,init_whileyhome};
 
 fn main() -> Result<(),Box<dyn Error>> {
@@ -10,9 +13,26 @@ fn main() -> Result<(),Box<dyn Error>> {
 	.about("Whiley Build Tool")
 	.version("0.6.0")
         .subcommand_required(true)
-	.arg(arg!(--verbose "Show verbose output"))
+	.arg(arg!(-v --verbose "Show verbose output"))
+	.arg(arg!(--offline "Run without accessing the network"))
+	.arg(arg!(--frozen "Require wy.lock is up to date (implies --offline)"))
+	.arg(arg!(--timeout <SECONDS> "Kill any JVM running for longer than this (0 for none)")
+	     .required(false)
+	     .value_parser(clap::value_parser!(u64)))
 	.subcommand(
 	    Command::new("build").about("Build local package(s)"))
+	.subcommand(
+	    Command::new("cache").about("Manage cached artifacts in WHILEYHOME")
+		.subcommand_required(true)
+		.subcommand(
+		    Command::new("list").about("List cached artifacts"))
+		.subcommand(
+		    Command::new("verify").about("Check cached artifacts against their checksums"))
+		.subcommand(
+		    Command::new("clean").about("Remove artifacts not used recently")
+			.arg(arg!(--"older-than" <AGE> "Remove artifacts unused for this long (e.g. 30d)")))
+		.subcommand(
+		    Command::new("gc").about("Remove artifacts not used by any recent project")))
 	.subcommand(
 	    Command::new("clean").about("Remove all generated (binary) files"))
 	.subcommand(
@@ -20,10 +40,41 @@ fn main() -> Result<(),Box<dyn Error>> {
 	.subcommand(
 	    Command::new("install").about("Install package in local repository"))
 	.subcommand(
-	    Command::new("run").about("Run package (via interpreter)"))
+	    Command::new("publish").about("Publish package to registry"))
+	.subcommand(
+	    Command::new("run").about("Run package (via interpreter)")
+		.arg(arg!(-p --package <NAME> "Workspace member to run").required(false)))
+	.subcommand(
+	    Command::new("toolchain").about("Manage installed toolchains in WHILEYHOME")
+		.subcommand_required(true)
+		.subcommand(
+		    Command::new("list").about("List installed toolchains"))
+		.subcommand(
+		    Command::new("install").about("Install toolchain for a given compiler version")
+			.arg(arg!(<VERSION> "Version of wyc"))
+			.arg(arg!(--js <VERSION> "Version of wyjs").required(false))
+			.arg(arg!(--boogie <VERSION> "Version of wyboogie").required(false)))
+		.subcommand(
+		    Command::new("remove").about("Remove an installed toolchain")
+			.arg(arg!(<VERSION> "Toolchain to remove")))
+		.subcommand(
+		    Command::new("default").about("Set the default toolchain")
+			.arg(arg!(<VERSION> "Toolchain to use by default"))))
+	.subcommand(
+	    Command::new("vendor").about("Copy all dependencies into the package"))
 	.get_matches();
     // Extract top-level flags
     let verbose = matches.is_present("verbose");
+    if matches.is_present("frozen") {
+	fetch::set_mode(Mode::Frozen);
+    } else if matches.is_present("offline") {
+	fetch::set_mode(Mode::Offline);
+    }
+    if let Some(t) = matches.get_one::<u64>("timeout") {
+	jvm::set_timeout(*t);
+    }
+    // Ensure JVMs do not outlive wy
+    jvm::init_interrupts()?;
     // Initialise logging
     if verbose {
 	init_logging(LevelFilter::Info);
@@ -33,10 +84,32 @@ fn main() -> Result<(),Box<dyn Error>> {
     // Dispatch on outcome
     let ok = match matches.subcommand() {
 	Some(("build", _)) => build(&whileyhome),
+	Some(("cache", args)) => {
+	    let command = match args.subcommand() {
+		Some(("list", _)) => CacheCommand::List,
+		Some(("verify", _)) => CacheCommand::Verify,
+		Some(("clean", a)) => CacheCommand::Clean(parse_age(a.value_of("older-than").unwrap())?),
+		Some(("gc", _)) => CacheCommand::Gc,
+		_ => unreachable!()
+	    };
+	    cache(&whileyhome,command)
+	}
 	Some(("clean", _)) => clean(&whileyhome),
 	Some(("init", _)) => init(&whileyhome),
 	Some(("install", _)) => install(&whileyhome),
-	Some(("run", _)) => run(&whileyhome),
+	Some(("publish", _)) => publish(&whileyhome),
+	Some(("run", args)) => run(&whileyhome,args.value_of("package")),
+	Some(("toolchain", args)) => {
+	    let command = match args.subcommand() {
+		Some(("list", _)) => ToolchainCommand::List,
+		Some(("install", a)) => ToolchainCommand::Install(a.value_of("VERSION").unwrap(),a.value_of("js"),a.value_of("boogie")),
+		Some(("remove", a)) => ToolchainCommand::Remove(a.value_of("VERSION").unwrap()),
+		Some(("default", a)) => ToolchainCommand::Default(a.value_of("VERSION").unwrap()),
+		_ => unreachable!()
+	    };
+	    toolchain(&whileyhome,command)
+	}
+	Some(("vendor", _)) => vendor(&whileyhome),
 	_ => unreachable!()
     }?;
     // Determine appropriate exit code
@@ -1,16 +1,26 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error;
-use std::fs::{read_to_string,create_dir_all};
-use std::path::Path;
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::{File,read_to_string,create_dir_all};
+use std::io::{Seek,Write,copy};
+use std::path::{Component,Path,PathBuf};
 use log::{info};
 use reqwest::Url;
-use crate::{init_classpath};
+use crate::{cache_layers,init_artifacts,init_classpath,init_java,init_overrides,init_registry};
+use crate::cache;
+use crate::fetch;
+use crate::fetch::Mode;
+use crate::index;
 use crate::util;
-use crate::config::{Config,Key,Error};
-use crate::jvm::{Jvm};
-use crate::package::{Dependency, PackageResolver};
+use crate::config::{Config,Key,Error,Type};
+use crate::jvm;
+use crate::jvm::{Jvm,JvmOptions};
+use crate::maven;
+use crate::lock::{ChecksumError,FrozenError,Lock,LockedArtifact,LockedPackage,LOCK_FILE};
+use crate::package::{Dependency, PackageResolver, PathDependency};
 use crate::platform;
-use crate::platform::{Instance,JavaInstance};
+use crate::platform::{Instance,JavaInstance,PluginError};
 
 // ===================================================================
 // Keys
@@ -20,10 +30,16 @@ pub static PACKAGE_NAME : Key = Key::new(&["package","name"]);
 pub static PACKAGE_AUTHORS : Key = Key::new(&["package","authors"]);
 pub static PACKAGE_VERSION : Key = Key::new(&["package","version"]);
 pub static BUILD_PLATFORMS : Key = Key::new(&["build","platforms"]);
+pub static BUILD_TARGET : Key = Key::new(&["build","target"]);
 pub static DEPENDENCIES : Key = Key::new(&["dependencies"]);
+pub static REGISTRY_URL : Key = Key::new(&["registry","url"]);
+pub static MAVEN_REPOSITORIES : Key = Key::new(&["maven","repositories"]);
+pub static MAVEN_OVERRIDES : Key = Key::new(&["maven","overrides"]);
+pub static VENDOR_DIRECTORY : Key = Key::new(&["vendor","directory"]);
+pub static JVM_JAVA : Key = Key::new(&["jvm","java"]);
 
 /// Default URL from which to resolve package dependencies.
-const PACKAGE_CENTRAL : &str = "https://github.com/Whiley/Repository/raw/master/";
+pub const PACKAGE_CENTRAL : &str = "https://github.com/Whiley/Repository/raw/master/";
 
 // ===================================================================
 // Result
@@ -52,10 +68,11 @@ impl Marker {
     pub fn new(kind: Kind, path: PathBuf, start: usize, end: usize, message: String) -> Self {
 	Marker{kind,path,start,end,message}
     }
-    /// Determine enclosing line information for the given marker
-    pub fn enclosing_line(&self) -> Result<Line,Box<dyn error::Error>> {
+    /// Determine enclosing line information for the given marker,
+    /// whose path is relative to a given package directory.
+    pub fn enclosing_line(&self, dir: &Path) -> Result<Line,Box<dyn error::Error>> {
 	// Read marked file
-	let contents = read_to_string(self.path.as_path())?;
+	let contents = read_to_string(dir.join(&self.path))?;
 	// Split into lines
 	let mut line = 1;
 	//
@@ -86,6 +103,9 @@ pub struct Line {
 /// Identifies meta-data about the package in question, such its name,
 /// version, etc.
 pub struct Build {
+    /// Identify the directory containing the package, against which
+    /// the paths it uses are resolved.  This is an absolute path.
+    pub dir: PathBuf,
     pub name: String,
     pub authors: Vec<String>,
     pub version: String,
@@ -93,18 +113,61 @@ pub struct Build {
     /// package.
     pub platforms: Vec<platform::Instance>,
     /// Identify dependencies for this build
-    pub dependencies: Vec<Dependency>
+    pub dependencies: Vec<Dependency>,
+    /// Identify dependencies for this build which are built from a
+    /// local directory.
+    pub path_dependencies: Vec<PathDependency>,
+    /// Identify the registry from which dependencies are resolved.
+    /// This is either a remote URL, or a local directory.
+    pub registry: Url,
+    /// Identify the Maven repositories (if any) from which the jars
+    /// needed by platforms are resolved.  These are tried in order,
+    /// before any configured for the user.
+    pub repositories: Vec<Url>,
+    /// Identify read-only caches (i.e. the vendor directory, if any)
+    /// searched for packages and jars before WHILEYHOME is used.
+    /// These are absolute paths.
+    pub layers: Vec<PathBuf>,
+    /// Identify the Java runtime configured for this build (if any).
+    /// This is an absolute path.
+    pub java: Option<PathBuf>,
+    /// Identify the options passed to the JVM (i.e. as given by
+    /// `[jvm]`).
+    pub jvm: JvmOptions,
+    /// Identify the options passed to the JVM for specific platforms
+    /// (i.e. as given by `[build.<platform>.jvm]`), which override
+    /// those above.
+    pub platform_jvm: HashMap<String,JvmOptions>,
+    /// Maven coordinates of the artifacts resolved for this build,
+    /// once it has been initialised.
+    resolved: RefCell<Option<Vec<String>>>
 }
 
 impl Build {
-    /// Parse a give string into a build configuration.
-    pub fn from_str<'a>(config: &Config, whileyhome: &Path, registry: &'a platform::Registry<'a>) -> Result<Build,Error> {
+    /// Parse a give string into a build configuration for the package
+    /// in a given (absolute) directory.
+    pub fn from_str<'a>(config: &Config, dir: &Path, whileyhome: &Path, registry: &'a platform::Registry<'a>) -> Result<Build,Error> {
         // Extract all required keys
         let name = config.get_string(&PACKAGE_NAME)?;
         let authors = config.get_string_array(&PACKAGE_AUTHORS)?;
         let version = config.get_string(&PACKAGE_VERSION)?;
         let platforms = config.get_string_array(&BUILD_PLATFORMS)?;
-	let deps = config.get_strings(&DEPENDENCIES).unwrap_or(Vec::new());
+        let url = config.get_string(&REGISTRY_URL).unwrap_or(PACKAGE_CENTRAL.to_string());
+        let url = match index::parse_root(&url) {
+            Some(url) => url,
+            None => {
+                return Err(Error::Invalid(REGISTRY_URL.to_string()));
+            }
+        };
+        let mut repositories = Vec::new();
+        for r in config.get_string_array(&MAVEN_REPOSITORIES).unwrap_or_default() {
+            match index::parse_root(&r) {
+                Some(url) => repositories.push(url),
+                None => {
+                    return Err(Error::Invalid(MAVEN_REPOSITORIES.to_string()));
+                }
+            }
+        }
         // Construct build information
         let mut ps = Vec::new();
         for p in &platforms {
@@ -114,30 +177,122 @@ impl Build {
                 }
                 Some(v) => v
             };
-            ps.push(init.apply(config,whileyhome)?);
+            ps.push(init.apply(config,dir,whileyhome)?);
+        }
+        // Map deps.  These are either version requirements
+        // (e.g. `std = "^0.3"`) or paths (e.g. `std = { path = "../std" }`).
+        let mut dependencies = Vec::new();
+        let mut path_dependencies = Vec::new();
+        for k in config.find_keys(&DEPENDENCIES).unwrap_or_default() {
+            let a = ["dependencies",k.as_str()];
+            let v = match config.get_string(&Key::new(&a)) {
+                Ok(v) => v,
+                Err(Error::Expected(_,_)) => {
+                    let b = [a[0],a[1],"path"];
+                    let path = config.get_path(&Key::new(&b))?;
+                    path_dependencies.push(PathDependency::new(k,path));
+                    continue;
+                }
+                Err(e) => { return Err(e); }
+            };
+            match Dependency::parse(k.clone(),&v) {
+                Ok(d) => dependencies.push(d),
+                Err(_) => {
+                    return Err(Error::Expected(Type::VersionReq,format!("{}.{}",DEPENDENCIES,k)));
+                }
+            }
+        }
+        // Determine vendor directory (if any)
+        let mut layers = Vec::new();
+        if let Ok(vendor) = config.get_path(&VENDOR_DIRECTORY) {
+            layers.push(dir.join(vendor));
+        }
+        // Determine Java runtime (if any).  NOTE: this is typically
+        // absolute, which get_path() does not preserve.
+        let java = match config.get_string(&JVM_JAVA) {
+            Ok(java) => Some(jvm::configured_java(dir,&java)),
+            Err(Error::Invalid(_)) => None,
+            Err(e) => { return Err(e); }
+        };
+        // Determine JVM options
+        let jvm = JvmOptions::from_config(config,&["jvm"])?;
+        let mut platform_jvm = HashMap::new();
+        for p in &ps {
+            if let Instance::Java(i) = p {
+                let options = JvmOptions::from_config(config,&["build",i.name(),"jvm"])?;
+                platform_jvm.insert(i.name().to_string(),options);
+            }
         }
-        // Map deps
-        let dependencies = deps.into_iter().map(|(k,v)| Dependency::new(k,v)).collect();
 	// Done
-	return Ok(Build{name,authors,version,platforms:ps,dependencies});
+	return Ok(Build{dir:dir.to_path_buf(),name,authors,version,platforms:ps,dependencies,path_dependencies,registry:url,repositories,layers,java,jvm,platform_jvm,resolved:RefCell::new(None)});
+    }
+
+    /// Determine the options to pass to the JVM when running a given
+    /// platform.  Those given in the environment take precedence.
+    pub fn jvm_options(&self, platform: &str) -> JvmOptions {
+        let options = match self.platform_jvm.get(platform) {
+            Some(o) => self.jvm.merge(o),
+            None => self.jvm.clone()
+        };
+        options.with_env()
     }
 
     /// Determine the list of know build artifacts.  This includes
-    /// source files, binary files and more.
+    /// source files, binary files and more.  Their paths are relative
+    /// to the package directory.
     pub fn manifest(&self) -> Manifest {
 	Manifest::new(self)
     }
 
+    /// Package this build into a zip file, as needed for installing
+    /// or publishing it.  This includes the build configuration,
+    /// source files and any binary files which should be distributed.
+    pub fn package<W: Write + Seek>(&self, out: W) -> Result<W,Box<dyn error::Error>> {
+        let mut zip = zip::ZipWriter::new(out);
+        //
+        for ba in self.manifest() {
+            // Extract path from artifact
+            match ba {
+                Artifact::SourceFolder(p) => {
+                    info!("Packaging source folder {}",p.display());
+                    zip.add_directory(entry_name(&p), Default::default())?;
+                }
+                Artifact::SourceFile(p) => {
+                    info!("Packaging source file {}",p.display());
+                    add_file(&self.dir,&p,&mut zip)?;
+                }
+                Artifact::BinaryFolder(p) => {
+                    info!("Packaging binary folder {}",p.display());
+                    zip.add_directory(entry_name(&p), Default::default())?;
+                }
+                Artifact::BinaryFile(p,f) => {
+                    if f {
+                        info!("Packaging binary file {}",p.display());
+                        add_file(&self.dir,&p,&mut zip)?;
+                    }
+                }
+            };
+        }
+        //
+        Ok(zip.finish()?)
+    }
+
     /// Run the given build.
     pub fn run(&self, whileyhome: &Path) -> Result<bool,Box<dyn error::Error>> {
 	// Perform startup initialisation(s)
-	self.initialise(whileyhome)?;
+	let whileypath = self.initialise(whileyhome)?;
+        // Execute the build
+        self.execute(whileyhome,&whileypath)
+    }
+
+    /// Execute each platform of this build using a given whileypath.
+    fn execute(&self, whileyhome: &Path, whileypath: &[String]) -> Result<bool,Box<dyn error::Error>> {
 	// Execute each platform in sequence.
 	for p in &self.platforms {
 	    // Execute plugin
 	    let result = match p {
 		Instance::Java(i) => {
-		    self.run_java(i.as_ref(),whileyhome)
+		    self.run_java(i.as_ref(),whileyhome,whileypath)
 		},
 		Instance::Rust(_) => {
 		    todo!("Rust platforms not currently supported")
@@ -149,7 +304,7 @@ impl Build {
 		    if markers.len() > 0 {
 			for m in markers {
 			    // Determine enclosing line!
-			    let l = m.enclosing_line()?;
+			    let l = m.enclosing_line(&self.dir)?;
 			    let f = m.path.into_os_string().into_string().unwrap();
 			    // Print out the error message
 			    println!("{}:{}:{}",f,l.line,m.message);
@@ -175,34 +330,96 @@ impl Build {
     }
 
     /// Run a Java platform
-    fn run_java(&self, i: &dyn JavaInstance, whileyhome: &Path) -> Result<Vec<Marker>,Box<dyn error::Error>> {
+    fn run_java(&self, i: &dyn JavaInstance, whileyhome: &Path, whileypath: &[String]) -> Result<Vec<Marker>,Box<dyn error::Error>> {
 	// Initialise classpath as necessary.  This will download Jar
 	// files from Maven central (if not already cached).
-	let cp = init_classpath(&whileyhome,i.dependencies())?;
+	let cp = init_classpath(whileyhome,&self.repositories,&self.layers,i.dependencies())?;
+        // Find suitable Java runtime
+        let java = init_java(whileyhome,self.java.as_deref(),i.java_version())?;
         // Construct JVM runner
-        let jvm = Jvm::new(cp,vec![("WHILEYHOME",&whileyhome)]);
+        let jvm = Jvm::new(java,cp,vec![("WHILEYHOME",&whileyhome)]).with_options(&self.jvm_options(i.name())).in_dir(&self.dir);
         // Construct command-line arguments
-        let args : Vec<String> = i.arguments();
+        let args : Vec<String> = i.arguments(whileypath);
         // Convert into Vec<&str> for exec
         let str_args : Vec<&str> = args.iter().map(String::as_str).collect();
         // Log Java command
         info!("Executing java {:?}",str_args);
-        // Go!
-        let output = jvm.exec(&str_args);
-	// Log output returned from Java
-	info!("Java output \"{}\"",output.as_str());
+        // Go!  Output is forwarded as it is produced, so that progress
+        // can be followed, except for that consumed when processing it
+        // (e.g. diagnostics) which is reported afterwards.
+        let name = i.name();
+        let output = jvm.exec(&str_args,|l| if !i.consumes(l) { eprintln!("{}",l) }).map_err(|e| {
+            PluginError{name:name.to_string(),message:e.to_string()}
+        })?;
+	// Log outcome
+	info!("Java exited with {}",output.status);
 	// Post process the response
-	i.process(output.as_str())
+	i.process(&output)
     }
 
     /// Perform necessary initialisation for this build
-    /// (e.g. downloading dependencies, etc).
-    fn initialise(&self, whileyhome: &Path) -> Result<(),Box<dyn error::Error>> {
+    /// (e.g. downloading dependencies, etc).  This returns the
+    /// whileypath to use when running the build.  Packages and Maven
+    /// artifacts are checked against those recorded in the lock file
+    /// (if it exists), and the lock file is then updated accordingly.
+    pub fn initialise(&self, whileyhome: &Path) -> Result<Vec<String>,Box<dyn error::Error>> {
+        // Read lock file (if it exists)
+        let path = self.dir.join(LOCK_FILE);
+        let mut lock = Lock::load(&path)?;
+        //
+        let whileypath = self.initialise_with(whileyhome,&mut lock)?;
+        // Record what was used
+        lock.prune(&self.dependencies,&self.coordinates(whileyhome)?);
+        lock.write(&path)?;
+        // Done
+        Ok(whileypath)
+    }
+
+    /// Perform necessary initialisation for this build using a given
+    /// lock (e.g. one shared by the members of a workspace).  Any
+    /// packages or artifacts used are recorded in the lock, though
+    /// nothing is removed from it.
+    pub fn initialise_with(&self, whileyhome: &Path, lock: &mut Lock) -> Result<Vec<String>,Box<dyn error::Error>> {
         self.create_binary_folders()?;
         //
-        self.resolve_packages(whileyhome)?;
+        let mut whileypath = resolve_packages(&self.dependencies,&self.registry,&self.layers,whileyhome,lock)?;
+        //
+        let jars = self.resolve_artifacts(whileyhome,lock)?;
+        // Record which cached files were used
+        let mut used : Vec<PathBuf> = whileypath.iter().map(PathBuf::from).collect();
+        used.extend(jars);
+        cache::record_usage(whileyhome,&self.dir,&used)?;
+        // Build any path dependencies
+        for d in &self.path_dependencies {
+            for e in self.build_path_dependency(d,whileyhome)? {
+                if !whileypath.contains(&e) {
+                    whileypath.push(e);
+                }
+            }
+        }
         // Done
-        Ok(())
+        Ok(whileypath)
+    }
+
+    /// Determine the Maven coordinates of all artifacts required by
+    /// the platforms of this build (including transitive ones).
+    /// Those resolved when initialising this build are reused, rather
+    /// than resolving them again.
+    pub fn coordinates(&self, whileyhome: &Path) -> Result<Vec<String>,Box<dyn error::Error>> {
+        if let Some(coordinates) = self.resolved.borrow().as_ref() {
+            return Ok(coordinates.clone());
+        }
+        let mut coordinates = Vec::new();
+        for p in &self.platforms {
+            if let Instance::Java(i) = p {
+                for (d,_) in init_artifacts(whileyhome,&self.repositories,&self.layers,i.dependencies())? {
+                    if !coordinates.contains(&d) {
+                        coordinates.push(d);
+                    }
+                }
+            }
+        }
+        Ok(coordinates)
     }
 
     /// Create binary folder(s) as necessary to store generated files.
@@ -212,9 +429,10 @@ impl Build {
 	    // Construct any missing binary folders.
 	    match ba {
 		Artifact::BinaryFolder(p) => {
-		    if !p.as_path().exists() {
+		    let path = self.dir.join(&p);
+		    if !path.exists() {
 			info!("Making binary folder {}",p.display());
-			create_dir_all(p)?;
+			create_dir_all(path)?;
 		    }
 		}
 		_ => {
@@ -225,24 +443,257 @@ impl Build {
         Ok(())
     }
 
-    /// Resolve all packages specified as dependencies.  This means
-    /// determining appropriate versions and, potentially, downloading
-    /// them.
-    fn resolve_packages(&self, whileyhome: &Path) -> Result<(),Box<dyn error::Error>> {
-        // Append repository into Whiley home
-        let mut repo = PathBuf::from(whileyhome);
-        repo.push("repository");
-        // Parse the base URL
-        let base_url = Url::parse(PACKAGE_CENTRAL).unwrap();
-        // Construct Package resolver
-        let resolver = PackageResolver::new(repo, base_url);
-	// Resolve package dependencies
-        resolver.resolve(&self.dependencies)?;
+    /// Build a package on which this package depends via a path
+    /// dependency.  The package is built in its own directory using
+    /// its own lock file.  This returns the whileypath entries needed
+    /// to use it, namely its zip file followed by its own whileypath.
+    fn build_path_dependency(&self, dep: &PathDependency, whileyhome: &Path) -> Result<Vec<String>,Box<dyn error::Error>> {
+        let dir = self.dir.join(dep.path()).canonicalize()?;
+        // Check whether already built (e.g. as a workspace member)
+        if let Some(whileypath) = lookup_built(&dir)? {
+            return Ok(whileypath);
+        }
+        info!("Building {}",dep);
+        // Read build configuration
+        let config_file = read_to_string(dir.join("wy.toml"))?;
+        let config = Config::from_str(config_file.as_str())?;
+        let registry = init_registry();
+        let mut build = Build::from_str(&config,&dir,whileyhome,&registry)?;
+        if build.name != dep.name() {
+            let message = format!("found package {} instead",build.name);
+            return Err(Box::new(DependencyError{dep:dep.to_string(),message}));
+        }
+        // Search this package's vendor directory as well
+        build.layers.extend(self.layers.iter().cloned());
+        // Build it
+        let path = dir.join(LOCK_FILE);
+        let mut lock = Lock::load(&path)?;
+        let whileypath = build.build_in(whileyhome,&mut lock)?;
+        lock.prune(&build.dependencies,&build.coordinates(whileyhome)?);
+        lock.write(&path)?;
+        match whileypath {
+            Some(wp) => Ok(wp),
+            None => {
+                let message = "build failed".to_string();
+                Err(Box::new(DependencyError{dep:dep.to_string(),message}))
+            }
+        }
+    }
+
+    /// Build this package using a given lock, and then package it
+    /// into a zip file within its binary folder.  This returns the
+    /// whileypath entries needed to use it (namely its zip file
+    /// followed by its own whileypath), or `None` if the build
+    /// failed.  Packages are only built once per invocation, and
+    /// attempting to build a package whilst it is being built (i.e. a
+    /// cycle of path dependencies) is reported as an error.
+    pub fn build_in(&self, whileyhome: &Path, lock: &mut Lock) -> Result<Option<Vec<String>>,Box<dyn error::Error>> {
+        let dir = self.dir.as_path();
+        if let Some(whileypath) = lookup_built(dir)? {
+            return Ok(Some(whileypath));
+        }
+        BUILT.with(|b| b.borrow_mut().insert(dir.to_path_buf(),None));
+        let result = self.build_and_package(whileyhome,lock);
+        // Record outcome
+        BUILT.with(|b| {
+            match &result {
+                Ok(Some(wp)) => { b.borrow_mut().insert(dir.to_path_buf(),Some(wp.clone())); }
+                _ => { b.borrow_mut().remove(dir); }
+            }
+        });
+        result
+    }
+
+    /// Build and then package this package (see `build_in()`).
+    fn build_and_package(&self, whileyhome: &Path, lock: &mut Lock) -> Result<Option<Vec<String>>,Box<dyn error::Error>> {
+        let mut whileypath = self.initialise_with(whileyhome,lock)?;
+        if !self.execute(whileyhome,&whileypath)? {
+            return Ok(None);
+        }
+        // Package it into its binary folder
+        let mut zip = self.dir.clone();
+        for ba in self.manifest() {
+            if let Artifact::BinaryFolder(p) = ba {
+                zip.push(p);
+                break;
+            }
+        }
+        zip.push(format!("{}-v{}.zip",self.name,self.version));
+        self.package(File::create(&zip)?)?;
+        let zip = zip.canonicalize()?;
+        // FIXME: whileypath should be Vec of PathBuf
+        whileypath.insert(0,zip.into_os_string().into_string().unwrap());
+        Ok(Some(whileypath))
+    }
+
+    /// Resolve all Maven artifacts required by the platforms of this
+    /// build, checking them against those recorded in the lock file
+    /// (if applicable).  Overridden artifacts are not checked, and
+    /// any recorded for them are left as is.  This returns the jar
+    /// files used.
+    fn resolve_artifacts(&self, whileyhome: &Path, lock: &mut Lock) -> Result<Vec<PathBuf>,Box<dyn error::Error>> {
+        let overrides = init_overrides(whileyhome)?;
+        let mut coordinates = Vec::new();
+        let mut jars = Vec::new();
+        for p in &self.platforms {
+            if let Instance::Java(i) = p {
+                for (d,jar) in init_artifacts(whileyhome,&self.repositories,&self.layers,i.dependencies())? {
+                    if !coordinates.contains(&d) {
+                        coordinates.push(d.clone());
+                    }
+                    if maven::is_overridden(&overrides,&d) {
+                        continue;
+                    }
+                    let checksum = util::sha256(&jar)?;
+                    match lock.artifact(&d) {
+                        Some(a) if a.checksum != checksum => {
+                            return Err(Box::new(ChecksumError{name:d,path:jar}));
+                        }
+                        None if fetch::mode() == Mode::Frozen => {
+                            let message = format!("{} is not recorded in {}",d,LOCK_FILE);
+                            return Err(Box::new(FrozenError{message}));
+                        }
+                        _ => {}
+                    }
+                    lock.insert_artifact(LockedArtifact{coordinate:d,checksum});
+                    jars.push(jar);
+                }
+            }
+        }
+        // Remember what was resolved (see coordinates())
+        *self.resolved.borrow_mut() = Some(coordinates);
         // Done
-        Ok(())
+        Ok(jars)
+    }
+}
+
+/// Resolve all packages specified as dependencies.  This means
+/// determining appropriate versions and, potentially, downloading
+/// them.  If the lock file is consistent with the dependencies,
+/// then the versions it records are used rather than resolving
+/// again.  The result is the whileypath, which identifies every
+/// package (including transitive dependencies) needed to build
+/// a package.
+pub fn resolve_packages(dependencies: &[Dependency], registry: &Url, layers: &[PathBuf], whileyhome: &Path, lock: &mut Lock) -> Result<Vec<String>,Box<dyn error::Error>> {
+    // Append repository into Whiley home
+    let mut repo = PathBuf::from(whileyhome);
+    repo.push("repository");
+    // Construct Package resolver
+    let resolver = PackageResolver::new(repo, registry.clone())?.with_layers(cache_layers(layers,"repository"));
+    // Resolve package dependencies
+    let locked_packages = lock.resolve(dependencies);
+    let packages = match &locked_packages {
+        Some(ps) => {
+            info!("Using package versions from {}",LOCK_FILE);
+            ps.iter().map(LockedPackage::to_package).collect::<Result<Vec<_>,_>>()?
+        }
+        None if fetch::mode() == Mode::Frozen => {
+            let message = format!("package versions in {} would change",LOCK_FILE);
+            return Err(Box::new(FrozenError{message}));
+        }
+        None => resolver.resolve(dependencies)?
+    };
+    // Check packages against lock and construct whileypath
+    let zips = resolver.get_all(&packages)?;
+    let mut whileypath = Vec::new();
+    for (i,(p,zip)) in packages.iter().zip(zips).enumerate() {
+        let checksum = util::sha256(&zip)?;
+        if let Some(l) = lock.package(p.name()) {
+            if l.version == p.version().to_string() && l.checksum != checksum {
+                return Err(Box::new(ChecksumError{name:p.to_string(),path:zip}));
+            }
+        }
+        let name = p.name().to_string();
+        let version = p.version().to_string();
+        let source = resolver.url(p).to_string();
+        let dependencies = match &locked_packages {
+            Some(ps) => ps[i].dependencies.clone(),
+            None => resolver.dependencies(p).map_err(|e| e as Box<dyn error::Error>)?.iter().map(|d| d.name().to_string()).collect()
+        };
+        lock.insert_package(LockedPackage{name,version,source,checksum,dependencies});
+        // FIXME: whileypath should be Vec of PathBuf
+        whileypath.push(zip.into_os_string().into_string().unwrap());
+    }
+    // Done
+    Ok(whileypath)
+}
+
+thread_local! {
+    /// Packages built from local directories during this invocation,
+    /// mapping each directory to the whileypath entries needed to use
+    /// it.  `None` indicates the package is currently being built.
+    static BUILT : RefCell<HashMap<PathBuf,Option<Vec<String>>>> = RefCell::new(HashMap::new());
+}
+
+/// Check whether the package in a given directory has already been
+/// built, returning the whileypath entries needed to use it if so.
+fn lookup_built(dir: &Path) -> Result<Option<Vec<String>>,Box<dyn error::Error>> {
+    match BUILT.with(|b| b.borrow().get(dir).cloned()) {
+        Some(Some(whileypath)) => Ok(Some(whileypath)),
+        Some(None) => {
+            let message = "cyclic path dependency".to_string();
+            Err(Box::new(DependencyError{dep:dir.display().to_string(),message}))
+        }
+        None => Ok(None)
     }
 }
 
+/// Check whether a given whileypath entry is a package built from a
+/// local directory during this invocation (e.g. for a path
+/// dependency), rather than one resolved from a registry.
+pub fn is_built(entry: &str) -> bool {
+    BUILT.with(|b| b.borrow().values().flatten().any(|wp| wp.first().is_some_and(|e| e == entry)))
+}
+
+/// Determine the name of the zip entry for a given file.  Binary
+/// files may live outside the package directory (e.g. in the shared
+/// binary folder of a workspace), hence any leading `..` components
+/// are dropped to keep all entries within the zip file.
+fn entry_name(path: &Path) -> String {
+    let p : PathBuf = path.components().filter(|c| !matches!(c,Component::ParentDir | Component::CurDir)).collect();
+    p.to_str().unwrap().to_string()
+}
+
+/// Add a given file (relative to a given package directory) to a zip
+/// file being constructed.
+fn add_file<T>(dir: &Path, path: &Path, zip: &mut zip::ZipWriter<T>)  -> Result<(),Box<dyn error::Error>>
+where T: Write + Seek
+{
+    // Create zip entry
+    let mut file = File::open(dir.join(path))?;
+    // Start Zip entry
+    zip.start_file(entry_name(path), Default::default())?;
+    // Copy all data over
+    copy(&mut file, zip)?;
+    // Done
+    Ok(())
+}
+
+// ===================================================================
+// Dependency Error
+// ===================================================================
+
+/// Indicates a path dependency could not be built.
+#[derive(Clone)]
+pub struct DependencyError {
+    dep: String,
+    message: String
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed building dependency {}: {}",self.dep,self.message)
+    }
+}
+
+impl fmt::Debug for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed building dependency {}: {}",self.dep,self.message)
+    }
+}
+
+impl error::Error for DependencyError {}
+
 // ===================================================================
 // Manifest
 // ===================================================================
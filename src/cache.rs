@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Component,Path,PathBuf};
+use std::time::{SystemTime,UNIX_EPOCH};
+use toml;
+use toml::Value;
+use toml::value::Table;
+use crate::config;
+use crate::config::Type;
+use crate::maven;
+use crate::maven::Pom;
+use crate::util;
+use crate::util::FileLock;
+
+/// Name of the Maven cache within WHILEYHOME.
+pub const MAVEN_DIR : &str = "maven";
+
+/// Name of the package cache within WHILEYHOME.
+pub const REPOSITORY_DIR : &str = "repository";
+
+/// Name of the usage database within WHILEYHOME.
+pub const USAGE_FILE : &str = "usage.toml";
+
+const HEADER : &str = "# This file is automatically generated by wy.\n# It records which projects used which cached artifacts, and when.\n\n";
+
+/// Kinds of checksum which may be recorded alongside a cached file.
+const CHECKSUMS : &[&str] = &["sha256","sha1"];
+
+// ===================================================================
+// Cached Artifact
+// ===================================================================
+
+/// An artifact held in one of the caches within WHILEYHOME, namely a
+/// jar in the Maven cache or a package zip in the package cache.
+pub struct CachedArtifact {
+    /// Location relative to WHILEYHOME (e.g.
+    /// `maven/org/whiley/wyc/0.10.18/wyc-0.10.18.jar`)
+    pub name: String,
+    /// Size of the artifact in bytes
+    pub size: u64,
+    /// Time the artifact was last modified, in seconds since the epoch
+    pub modified: u64
+}
+
+impl CachedArtifact {
+    /// Get the location of this artifact.
+    pub fn path(&self, whileyhome: &Path) -> PathBuf {
+        whileyhome.join(&self.name)
+    }
+
+    /// Check whether a checksum was recorded for this artifact.
+    pub fn has_checksum(&self, whileyhome: &Path) -> bool {
+        let path = self.path(whileyhome);
+        CHECKSUMS.iter().any(|ext| maven::checksum_path(&path,ext).exists())
+    }
+
+    /// Check this artifact against the checksum recorded for it (if
+    /// any).
+    pub fn verify(&self, whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+        maven::verify_cached(&self.path(whileyhome)).map_err(|e| e as Box<dyn Error>)
+    }
+
+    /// Remove this artifact from the cache, along with any files
+    /// associated with it (e.g. checksums, POMs and partial
+    /// downloads).
+    pub fn remove(&self, whileyhome: &Path) -> Result<(),Box<dyn Error>> {
+        let path = self.path(whileyhome);
+        let mut files = vec![path.clone(),maven::checksum_path(&path,"part")];
+        for ext in CHECKSUMS {
+            files.push(maven::checksum_path(&path,ext));
+        }
+        if self.name.ends_with(".jar") {
+            files.push(path.with_extension("pom"));
+        }
+        for f in files {
+            if f.exists() {
+                remove_file(whileyhome,&f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove a given file from the caches within WHILEYHOME, along with
+/// any directories this leaves empty (e.g. that of an artifact's
+/// version).
+pub fn remove_file(whileyhome: &Path, file: &Path) -> Result<(),Box<dyn Error>> {
+    fs::remove_file(file)?;
+    let roots = [whileyhome.join(MAVEN_DIR),whileyhome.join(REPOSITORY_DIR)];
+    let mut dir = file.parent();
+    while let Some(d) = dir {
+        if roots.iter().any(|r| r == d) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
+
+/// Determine all artifacts currently held in the caches within
+/// WHILEYHOME, ordered by name.
+pub fn artifacts(whileyhome: &Path) -> Result<Vec<CachedArtifact>,Box<dyn Error>> {
+    let mut artifacts = Vec::new();
+    for (dir,ext) in [(MAVEN_DIR,"jar"),(REPOSITORY_DIR,"zip")] {
+        for path in util::files_within(&whileyhome.join(dir))? {
+            if path.extension().is_some_and(|x| x == ext) {
+                let metadata = fs::metadata(&path)?;
+                let name = relative_name(whileyhome,&path).unwrap();
+                let modified = seconds(metadata.modified()?);
+                artifacts.push(CachedArtifact{name,size:metadata.len(),modified});
+            }
+        }
+    }
+    artifacts.sort_by(|a,b| a.name.cmp(&b.name));
+    Ok(artifacts)
+}
+
+/// Determine all files in the caches within WHILEYHOME which belong
+/// to no cached artifact, such as checksums or partial downloads of
+/// removed artifacts.  Likewise, a POM is orphaned unless it belongs
+/// to a cached jar or is the parent of such a POM (since it is
+/// needed to resolve dependencies).
+pub fn orphans(whileyhome: &Path) -> Result<Vec<PathBuf>,Box<dyn Error>> {
+    let mut orphans = Vec::new();
+    for dir in [MAVEN_DIR,REPOSITORY_DIR] {
+        let dir = whileyhome.join(dir);
+        let names : Vec<String> = util::files_within(&dir)?.iter().filter_map(|f| relative_name(&dir,f)).collect();
+        // Determine POMs which are needed
+        let mut poms : Vec<String> = names.iter().filter_map(|n| n.strip_suffix(".jar")).map(|n| format!("{}.pom",n)).collect();
+        let mut i = 0;
+        while i < poms.len() {
+            if let Ok(contents) = fs::read_to_string(dir.join(&poms[i])) {
+                if let Some(parent) = Pom::parse(&contents).and_then(|p| p.parent) {
+                    let name = parent.to_pompath();
+                    if !poms.contains(&name) {
+                        poms.push(name);
+                    }
+                }
+            }
+            i += 1;
+        }
+        // Identify everything else
+        for n in &names {
+            let orphan = if n.ends_with(".pom") {
+                !poms.contains(n)
+            } else {
+                let mut exts = CHECKSUMS.iter().chain(&["part"]);
+                match exts.find_map(|ext| n.strip_suffix(&format!(".{}",ext))) {
+                    Some(base) => !names.iter().any(|m| m == base),
+                    None => false
+                }
+            };
+            if orphan {
+                orphans.push(dir.join(n));
+            }
+        }
+    }
+    Ok(orphans)
+}
+
+/// Acquire the locks needed to modify the caches within WHILEYHOME.
+/// These are always acquired in the same order (i.e. Maven cache,
+/// package cache then usage database) to avoid deadlock.
+pub fn lock_all(whileyhome: &Path) -> Result<Vec<FileLock>,Box<dyn Error>> {
+    Ok(vec![
+        FileLock::acquire(&whileyhome.join(MAVEN_DIR))?,
+        FileLock::acquire(&whileyhome.join(REPOSITORY_DIR))?,
+        FileLock::acquire(whileyhome)?
+    ])
+}
+
+// ===================================================================
+// Usage
+// ===================================================================
+
+/// Records which projects have used which cached artifacts, and
+/// when.  This is stored in `usage.toml` within WHILEYHOME, and
+/// determines what can be safely garbage collected.  For example:
+///
+/// ```toml
+/// [used."maven/org/whiley/wyc/0.10.18/wyc-0.10.18.jar"]
+/// "/home/dave/projects/std" = 1760000000
+/// ```
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Usage {
+    /// Maps each artifact to the projects which used it, along with
+    /// the time each did so last (in seconds since the epoch).
+    pub used: BTreeMap<String,BTreeMap<String,u64>>
+}
+
+impl Usage {
+    /// Read the usage database at a given path, which is empty if no
+    /// such file exists.
+    pub fn read(path: &Path) -> Result<Usage,Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Usage::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        let toml: Value = toml::from_str(&contents).map_err(config::Error::from)?;
+        let mut used = BTreeMap::new();
+        if let Some(t) = toml.get("used") {
+            let t = t.as_table().ok_or_else(|| config::Error::Invalid("used".to_string()))?;
+            for (artifact,projects) in t {
+                let key = format!("used.{}",artifact);
+                let projects = projects.as_table().ok_or_else(|| config::Error::Invalid(key.clone()))?;
+                let mut ps = BTreeMap::new();
+                for (p,time) in projects {
+                    let time = time.as_integer().ok_or_else(|| config::Error::Expected(Type::Int,key.clone()))?;
+                    ps.insert(p.clone(),time as u64);
+                }
+                used.insert(artifact.clone(),ps);
+            }
+        }
+        Ok(Usage{used})
+    }
+
+    /// Write this usage database to a given path.
+    pub fn write(&self, path: &Path) -> Result<(),Box<dyn Error>> {
+        let mut used = Table::new();
+        for (artifact,projects) in &self.used {
+            let mut t = Table::new();
+            for (p,time) in projects {
+                t.insert(p.clone(),Value::Integer(*time as i64));
+            }
+            used.insert(artifact.clone(),Value::Table(t));
+        }
+        let mut root = Table::new();
+        root.insert("used".to_string(),Value::Table(used));
+        fs::write(path,format!("{}{}",HEADER,toml::to_string(&Value::Table(root))?))?;
+        Ok(())
+    }
+
+    /// Determine when a given artifact was last used by any project
+    /// (if ever).
+    pub fn last_used(&self, artifact: &str) -> Option<u64> {
+        self.used.get(artifact).and_then(|ps| ps.values().max().copied())
+    }
+}
+
+/// Record that the project in a given directory used a given set of
+/// cached files (e.g. jars or package zips) within WHILEYHOME.
+pub fn record_usage(whileyhome: &Path, dir: &Path, files: &[PathBuf]) -> Result<(),Box<dyn Error>> {
+    let project = dir.to_string_lossy().to_string();
+    let now = now();
+    let _lock = FileLock::acquire(whileyhome)?;
+    let path = whileyhome.join(USAGE_FILE);
+    let mut usage = Usage::read(&path)?;
+    for f in files {
+        if let Some(name) = relative_name(whileyhome,f) {
+            usage.used.entry(name).or_default().insert(project.clone(),now);
+        }
+    }
+    usage.write(&path)
+}
+
+/// Determine the name of a cached file relative to a given directory
+/// such as WHILEYHOME (e.g. `maven/org/whiley/wyc/0.10.18/wyc-0.10.18.jar`),
+/// or `None` if it is not within that directory.
+fn relative_name(dir: &Path, file: &Path) -> Option<String> {
+    let rel = file.strip_prefix(dir).ok()?;
+    let parts : Vec<String> = rel.components().filter_map(|c| match c {
+        Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+        _ => None
+    }).collect();
+    Some(parts.join("/"))
+}
+
+/// Get the current time in seconds since the epoch.
+pub fn now() -> u64 {
+    seconds(SystemTime::now())
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// ===================================================================
+// Tests
+// ===================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    fn touch(dir: &Path, name: &str, contents: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path,contents).unwrap();
+    }
+
+    #[test]
+    fn find_orphans() {
+        let whileyhome = util::test_dir("find_orphans");
+        let maven = whileyhome.join(MAVEN_DIR);
+        let repository = whileyhome.join(REPOSITORY_DIR);
+        fs::create_dir_all(&maven).unwrap();
+        fs::create_dir_all(&repository).unwrap();
+        // Cached jar with its POM, whose parent is also needed
+        touch(&maven,"org/whiley/wyc/0.10.18/wyc-0.10.18.jar","");
+        touch(&maven,"org/whiley/wyc/0.10.18/wyc-0.10.18.jar.sha256","");
+        touch(&maven,"org/whiley/wyc/0.10.18/wyc-0.10.18.pom","<project><parent><groupId>org.whiley</groupId><artifactId>parent</artifactId><version>1.0</version></parent></project>");
+        touch(&maven,"org/whiley/parent/1.0/parent-1.0.pom","<project/>");
+        touch(&repository,"std-v0.3.5.zip","");
+        touch(&repository,"std-v0.3.5.zip.sha256","");
+        // Left behind by removed artifacts
+        touch(&maven,"org/whiley/old/1.0/old-1.0.pom","<project/>");
+        touch(&maven,"org/whiley/old/1.0/old-1.0.jar.sha1","");
+        touch(&maven,"org/whiley/new/1.0/new-1.0.jar.part","");
+        touch(&repository,"std-v0.3.2.zip.sha256","");
+        //
+        let mut orphans = orphans(&whileyhome).unwrap();
+        orphans.sort();
+        let expected = vec![
+            maven.join("org/whiley/new/1.0/new-1.0.jar.part"),
+            maven.join("org/whiley/old/1.0/old-1.0.jar.sha1"),
+            maven.join("org/whiley/old/1.0/old-1.0.pom"),
+            repository.join("std-v0.3.2.zip.sha256")
+        ];
+        assert_eq!(orphans,expected);
+        // Removing them leaves no empty directories behind
+        for f in &orphans {
+            remove_file(&whileyhome,f).unwrap();
+        }
+        assert!(!maven.join("org/whiley/old").exists());
+        assert!(maven.join("org/whiley/wyc/0.10.18").exists());
+        assert!(repository.exists());
+    }
+}
@@ -1,8 +1,10 @@
+use std::env;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
 use crate::config::Config;
 use crate::build::{Build};
+use crate::workspace::Workspace;
 use crate::{init_registry};
 
 // Build command
@@ -14,8 +16,12 @@ pub fn build(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
     let config = Config::from_str(config_file.as_str())?;    
    // Initialise platform registry
     let registry = init_registry();    
+    // Build all members of a workspace (if applicable)
+    if let Some(ws) = Workspace::from_config(&config,whileyhome,&registry)? {
+        return Ok(ws.build(whileyhome)?.is_some());
+    }
     // Construct build plan
-    let build = Build::from_str(&config,whileyhome,&registry)?;
+    let build = Build::from_str(&config,&env::current_dir()?,whileyhome,&registry)?;
     // Go!
     let r = build.run(whileyhome)?;
     // Respond with command result
@@ -0,0 +1,216 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use log::info;
+use crate::cache;
+use crate::cache::{CachedArtifact,Usage,USAGE_FILE};
+
+/// Number of seconds in a day.
+const DAY : u64 = 24 * 60 * 60;
+
+/// Artifacts not used by any project within this many days are
+/// removed by garbage collection.
+const RECENT_DAYS : u64 = 30;
+
+/// Operations supported by the cache command.
+pub enum CacheCommand {
+    /// List all cached artifacts
+    List,
+    /// Check all cached artifacts against their recorded checksums
+    Verify,
+    /// Remove artifacts not used within a given number of seconds
+    Clean(u64),
+    /// Remove artifacts not used by any (existing) recent project
+    Gc
+}
+
+// Cache command
+pub fn cache(whileyhome: &Path, command: CacheCommand) -> Result<bool,Box<dyn Error>> {
+    match command {
+        CacheCommand::List => list(whileyhome),
+        CacheCommand::Verify => verify(whileyhome),
+        CacheCommand::Clean(age) => clean(whileyhome,age),
+        CacheCommand::Gc => gc(whileyhome)
+    }
+}
+
+/// Parse an age given on the command-line, such as `30d`, `12h` or
+/// `2w`.  A plain number is taken as days.  The result is in seconds.
+pub fn parse_age(age: &str) -> Result<u64,Box<dyn Error>> {
+    let (n,unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => age.split_at(i),
+        None => (age,"d")
+    };
+    let scale = match unit {
+        "h" => 60 * 60,
+        "d" => DAY,
+        "w" => 7 * DAY,
+        _ => { return Err(Box::new(AgeError{age:age.to_string()})); }
+    };
+    match n.parse::<u64>() {
+        Ok(n) => Ok(n * scale),
+        Err(_) => Err(Box::new(AgeError{age:age.to_string()}))
+    }
+}
+
+/// List every cached artifact, along with its size and when it was
+/// last used.
+fn list(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+    let usage = Usage::read(&whileyhome.join(USAGE_FILE))?;
+    let artifacts = cache::artifacts(whileyhome)?;
+    let now = cache::now();
+    let width = artifacts.iter().map(|a| a.name.len()).max().unwrap_or(0);
+    let mut total = 0;
+    for a in &artifacts {
+        let used = match usage.last_used(&a.name) {
+            Some(t) => format!("used {}",ago(now,t)),
+            None => "never used".to_string()
+        };
+        println!("{:<width$} {:>10} {}",a.name,size(a.size),used);
+        total += a.size;
+    }
+    println!("{} artifact(s), {}",artifacts.len(),size(total));
+    Ok(true)
+}
+
+/// Check every cached artifact against the checksum recorded for it
+/// when it was downloaded.  This fails if any artifact is corrupt.
+fn verify(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+    let _locks = cache::lock_all(whileyhome)?;
+    let mut ok = true;
+    let artifacts = cache::artifacts(whileyhome)?;
+    let width = artifacts.iter().map(|a| a.name.len()).max().unwrap_or(0);
+    for a in artifacts {
+        let status = if !a.has_checksum(whileyhome) {
+            "unverified"
+        } else if a.verify(whileyhome)? {
+            "ok"
+        } else {
+            ok = false;
+            "CORRUPT"
+        };
+        println!("{:<width$} {}",a.name,status);
+    }
+    Ok(ok)
+}
+
+/// Remove every cached artifact which has not been used within a
+/// given number of seconds.  Artifacts never recorded as used are
+/// judged by when they were downloaded.
+fn clean(whileyhome: &Path, age: u64) -> Result<bool,Box<dyn Error>> {
+    let _locks = cache::lock_all(whileyhome)?;
+    let path = whileyhome.join(USAGE_FILE);
+    let mut usage = Usage::read(&path)?;
+    let now = cache::now();
+    let mut removed = Vec::new();
+    for a in cache::artifacts(whileyhome)? {
+        let last = usage.last_used(&a.name).unwrap_or(a.modified);
+        if now.saturating_sub(last) > age {
+            removed.push(a);
+        }
+    }
+    remove(whileyhome,&removed,&mut usage)?;
+    usage.write(&path)?;
+    Ok(true)
+}
+
+/// Remove every cached artifact which is not referenced by a recent
+/// project.  A project is recent if it still exists and used the
+/// artifact within the last `RECENT_DAYS` days.  References from
+/// projects which no longer exist are forgotten.  Artifacts never
+/// recorded as used (e.g. those cached by an older version) are
+/// judged by when they were downloaded.  Finally, any files
+/// belonging to no cached artifact are removed.
+fn gc(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+    let _locks = cache::lock_all(whileyhome)?;
+    let path = whileyhome.join(USAGE_FILE);
+    let mut usage = Usage::read(&path)?;
+    let now = cache::now();
+    // Forget projects which no longer exist
+    for projects in usage.used.values_mut() {
+        projects.retain(|p,_| Path::new(p).join("wy.toml").exists());
+    }
+    // Determine unreferenced artifacts
+    let mut removed = Vec::new();
+    for a in cache::artifacts(whileyhome)? {
+        let recent = match usage.used.get(&a.name) {
+            Some(ps) => ps.values().any(|t| now.saturating_sub(*t) <= RECENT_DAYS * DAY),
+            None => now.saturating_sub(a.modified) <= RECENT_DAYS * DAY
+        };
+        if !recent {
+            removed.push(a);
+        }
+    }
+    remove(whileyhome,&removed,&mut usage)?;
+    // Remove anything left behind
+    let orphans = cache::orphans(whileyhome)?;
+    for f in &orphans {
+        info!("Removing {}",f.display());
+        cache::remove_file(whileyhome,f)?;
+    }
+    if !orphans.is_empty() {
+        println!("Removed {} orphaned file(s)",orphans.len());
+    }
+    // Forget artifacts which are no longer cached
+    usage.used.retain(|a,ps| !ps.is_empty() && whileyhome.join(a).exists());
+    usage.write(&path)?;
+    Ok(true)
+}
+
+/// Remove a given set of artifacts from the cache, reporting the
+/// space reclaimed.
+fn remove(whileyhome: &Path, artifacts: &[CachedArtifact], usage: &mut Usage) -> Result<(),Box<dyn Error>> {
+    let mut total = 0;
+    for a in artifacts {
+        info!("Removing {}",a.name);
+        a.remove(whileyhome)?;
+        usage.used.remove(&a.name);
+        total += a.size;
+    }
+    println!("Removed {} artifact(s), {}",artifacts.len(),size(total));
+    Ok(())
+}
+
+/// Format a given number of bytes for display.
+fn size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MiB",bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KiB",bytes as f64 / 1024.0)
+    } else {
+        format!("{} B",bytes)
+    }
+}
+
+/// Format how long ago a given time was for display.
+fn ago(now: u64, time: u64) -> String {
+    let secs = now.saturating_sub(time);
+    if secs < DAY {
+        "today".to_string()
+    } else {
+        format!("{} day(s) ago",secs / DAY)
+    }
+}
+
+// ================================================================
+// Age Error
+// ================================================================
+
+#[derive(Clone)]
+pub struct AgeError {
+    pub age: String
+}
+
+impl fmt::Display for AgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid age \"{}\" (expected e.g. 30d, 12h or 2w)",self.age)
+    }
+}
+
+impl fmt::Debug for AgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid age \"{}\" (expected e.g. 30d, 12h or 2w)",self.age)
+    }
+}
+
+impl Error for AgeError {}
@@ -1,9 +1,11 @@
+use std::env;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
 use log::info;
 use crate::config::Config;
 use crate::build::{Artifact,Build};
+use crate::workspace::Workspace;
 use crate::{init_registry};
 
 // Clean command
@@ -14,22 +16,35 @@ pub fn clean(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
     let config = Config::from_str(config_file.as_str())?;    
    // Initialise platform registry
     let registry = init_registry();    
+    // Clean all members of a workspace (if applicable)
+    if let Some(ws) = Workspace::from_config(&config,whileyhome,&registry)? {
+        for m in &ws.members {
+            clean_build(&m.build)?;
+        }
+        return Ok(true);
+    }
     // Construct build plan
-    let build = Build::from_str(&config,whileyhome,&registry)?;
+    let build = Build::from_str(&config,&env::current_dir()?,whileyhome,&registry)?;
     // Clean all folders
+    clean_build(&build)?;
+    //
+    Ok(true)
+}
+
+/// Remove all binary folders for a given build.
+fn clean_build(build: &Build) -> Result<(),Box<dyn Error>> {
     for ba in build.manifest() {
 	match ba {
 	    Artifact::BinaryFolder(p) => {
-		if p.as_path().exists() {
+		let path = build.dir.join(&p);
+		if path.exists() {
 		    info!("Removing folder {}",p.display());
-		    fs::remove_dir_all(p)?;
+		    fs::remove_dir_all(path)?;
 		}
 	    }
 	    _ => {
 	    }
 	}
-    }    
-    //
-    Ok(true)
+    }
+    Ok(())
 }
-
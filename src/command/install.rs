@@ -1,10 +1,16 @@
+use std::env;
 use std::error::Error;
+use std::fmt;
+use std::fs;
 use std::fs::{File,read_to_string};
 use std::path::{Path,PathBuf};
-use std::io::{Read,Write,Seek,copy};
 use log::info;
 use crate::config::Config;
-use crate::build::{Artifact,Build};
+use crate::build::Build;
+use crate::maven;
+use crate::util;
+use crate::util::FileLock;
+use crate::workspace::Workspace;
 use crate::{init_registry};
 
 const REPOSITORY_NAME : &'static str = "repository";
@@ -16,54 +22,42 @@ pub fn install(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
     let config = Config::from_str(config_file.as_str())?;
    // Initialise platform registry
     let registry = init_registry();
+    // Install all members of a workspace (if applicable)
+    if let Some(ws) = Workspace::from_config(&config,whileyhome,&registry)? {
+        for m in &ws.members {
+            install_build(whileyhome,&m.build)?;
+        }
+        return Ok(true);
+    }
     // Construct build plan
-    let build = Build::from_str(&config,whileyhome,&registry)?;
-    // Construct zip file
+    let build = Build::from_str(&config,&env::current_dir()?,whileyhome,&registry)?;
+    install_build(whileyhome,&build)?;
+    Ok(true)
+}
+
+/// Install a given build into the local repository.
+fn install_build(whileyhome: &Path, build: &Build) -> Result<(),Box<dyn Error>> {
+    // Installed packages are resolved like published ones
+    if let Some(d) = build.path_dependencies.first() {
+        let message = format!("cannot install package with path dependency {}",d);
+        return Err(Box::new(InstallError{message}));
+    }
+    // Prevent concurrent use of the repository
+    let _lock = FileLock::acquire(&whileyhome.join(REPOSITORY_NAME))?;
+    // Construct zip file alongside, then move into place
     let pkg = format!("{}-v{}.zip",build.name,build.version);
     let path = get_pkg_path(whileyhome,&pkg);
-    let zipfile = File::create(path).unwrap();
-    let mut zip = zip::ZipWriter::new(zipfile);
-    //
-    for ba in build.manifest() {
-	// Extract path from artifact
-	match ba {
-	    Artifact::SourceFolder(p) => {
-		info!("Packaging source folder {}",p.display());
-		zip.add_directory(p.to_str().unwrap(), Default::default())?;
-	    }
-	    Artifact::SourceFile(p) => {
-		info!("Packaging source file {}",p.display());
-		add_file(&p,&mut zip)?;
-	    }
-	    Artifact::BinaryFolder(p) => {
-		info!("Packaging binary folder {}",p.display());
-		zip.add_directory(p.to_str().unwrap(), Default::default())?;
-	    }
-	    Artifact::BinaryFile(p,f) => {
-		if f {
-		    info!("Packaging binary file {}",p.display());
-		    add_file(&p,&mut zip)?;
-		}
-	    }
-	};
+    let mut part = path.clone().into_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    if let Err(e) = build.package(File::create(&part)?) {
+        fs::remove_file(&part)?;
+        return Err(e);
     }
-    //
-    zip.finish()?;
+    fs::rename(&part,&path)?;
+    // Record checksum for checking cached package later
+    fs::write(maven::checksum_path(&path,"sha256"),util::sha256(&path)?)?;
     info!("Installed {} ...",pkg);
-    Ok(true)
-}
-
-fn add_file<T>(buf: &PathBuf, zip: &mut zip::ZipWriter<T>)  -> Result<(),Box<dyn Error>>
-where T: Read + Write + Seek
-{
-    let path = buf.as_path();
-    // Create zip entry
-    let mut file = File::open(path)?;
-    // Start Zip entry
-    zip.start_file(path.to_str().unwrap(), Default::default())?;
-    // Copy all data over
-    copy(&mut file, zip)?;
-    // Done
     Ok(())
 }
 
@@ -74,3 +68,26 @@ fn get_pkg_path(whileyhome: &Path, name: &String) -> PathBuf {
     buf.push(name);
     return buf;
 }
+
+// ================================================================
+// Install Error
+// ================================================================
+
+#[derive(Clone)]
+struct InstallError {
+    message: String
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed installing package: {}",self.message)
+    }
+}
+
+impl fmt::Debug for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed installing package: {}",self.message)
+    }
+}
+
+impl Error for InstallError {}
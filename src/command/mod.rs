@@ -1,14 +1,22 @@
 mod build;
+mod cache;
 mod clean;
 mod init;
 mod install;
+mod publish;
 mod run;
+mod toolchain;
+mod vendor;
 
 pub use build::build;
+pub use cache::{cache,parse_age,CacheCommand};
 pub use clean::clean;
 pub use init::init;
 pub use install::install;
+pub use publish::publish;
 pub use run::run;
+pub use toolchain::{toolchain,ToolchainCommand};
+pub use vendor::vendor;
 
 // Help command
 
@@ -0,0 +1,248 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Cursor,ErrorKind,Write};
+use std::path::{Path,PathBuf};
+use log::info;
+use reqwest;
+use reqwest::{StatusCode,Url};
+use reqwest::header::{ETAG,IF_MATCH,IF_NONE_MATCH};
+use semver::Version;
+use crate::config::Config;
+use crate::build::Build;
+use crate::fetch;
+use crate::fetch::Fetcher;
+use crate::index;
+use crate::index::{Index,IndexEntry};
+use crate::package;
+use crate::util;
+use crate::util::FileLock;
+use crate::{init_registry};
+
+/// Number of attempts at updating the index of a remote registry
+/// whilst others are updating it.
+const MAX_ATTEMPTS : usize = 5;
+
+// Publish command
+pub fn publish(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+    // Publishing requires the registry
+    if fetch::mode().is_offline() {
+        let message = "cannot publish whilst offline".to_string();
+        return Err(Box::new(PublishError{message}));
+    }
+    // Read build configuration
+    let config_file = fs::read_to_string("wy.toml")?;
+    // Parse configuration
+    let config = Config::from_str(config_file.as_str())?;
+    // Initialise platform registry
+    let registry = init_registry();
+    // Construct build plan
+    let build = Build::from_str(&config,&env::current_dir()?,whileyhome,&registry)?;
+    // Sanity check package metadata
+    let version = validate(&build)?;
+    // Build package
+    if !build.run(whileyhome)? {
+        return Ok(false);
+    }
+    // Construct zip file
+    let zip = build.package(Cursor::new(Vec::new()))?.into_inner();
+    let checksum = util::sha256_bytes(&zip);
+    let zipname = format!("{}-v{}.zip",build.name,version);
+    let zipurl = build.registry.join(&format!("{}/{}/{}",build.name,version,zipname))?;
+    let indexurl = index::to_index_url(&build.registry,&build.name);
+    // Upload package (which must not already exist).  If it does
+    // exist, then an earlier attempt may have failed before updating
+    // the index.  In which case, the index entry is determined from
+    // the package uploaded then.
+    let entry = if upload(&zipurl,zip)? {
+        let dependencies = build.dependencies.clone();
+        IndexEntry{version:version.clone(),checksum,dependencies,yanked:false}
+    } else {
+        uploaded_entry(&zipurl,&version)?
+    };
+    // Add it to the index (unless already there)
+    if !update_index(&indexurl,entry)? {
+        return Err(Box::new(published(&build.name,&version)));
+    }
+    info!("Published {} ...",zipname);
+    Ok(true)
+}
+
+/// Check the package metadata is suitable for publishing, returning
+/// the version being published.
+fn validate(build: &Build) -> Result<Version,Box<dyn Error>> {
+    let valid_name = build.name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && build.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_name {
+        let message = format!("invalid package name \"{}\"",build.name);
+        return Err(Box::new(PublishError{message}));
+    }
+    if build.authors.is_empty() || build.authors.iter().any(|a| a.trim().is_empty()) {
+        let message = "package must have one or more (non-empty) authors".to_string();
+        return Err(Box::new(PublishError{message}));
+    }
+    if let Some(d) = build.path_dependencies.first() {
+        let message = format!("cannot publish package with path dependency {}",d);
+        return Err(Box::new(PublishError{message}));
+    }
+    match Version::parse(&build.version) {
+        Ok(v) => Ok(v),
+        Err(_) => {
+            let message = format!("invalid package version \"{}\"",build.version);
+            Err(Box::new(PublishError{message}))
+        }
+    }
+}
+
+/// Upload a given file to a registry, provided it does not already
+/// exist.  For a directory-backed registry (i.e. `file://`) this
+/// writes the file directly, whilst for a remote registry it is sent
+/// via an HTTP `PUT`.  This returns `false` if the file already
+/// exists.  Since the check is made by the registry itself (e.g. via
+/// `If-None-Match`), concurrent uploads cannot both succeed.
+fn upload(url: &Url, bytes: Vec<u8>) -> Result<bool,Box<dyn Error>> {
+    if url.scheme() == "file" {
+        let path = to_path(url)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => file.write_all(&bytes)?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => { return Ok(false); }
+            Err(e) => { return Err(Box::new(e)); }
+        }
+    } else {
+        let client = reqwest::blocking::Client::new();
+        let response = client.put(url.clone()).header(IF_NONE_MATCH,"*").body(bytes).send()?;
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            return Ok(false);
+        } else if !response.status().is_success() {
+            let message = format!("uploading {} ({:?})",url,response.status());
+            return Err(Box::new(PublishError{message}));
+        }
+    }
+    info!("Uploaded {}",url);
+    Ok(true)
+}
+
+/// Add a given entry to the index of a package in a registry,
+/// returning `false` if it already has an entry for that version.
+/// Since other versions may be published at the same time, the index
+/// is only replaced if unchanged since it was read.  For a
+/// directory-backed registry, this is ensured by locking the
+/// package's directory.  For a remote registry, the index is
+/// uploaded conditionally (i.e. via `If-Match`) and, if it has
+/// changed, the update is retried.
+fn update_index(url: &Url, entry: IndexEntry) -> Result<bool,Box<dyn Error>> {
+    if url.scheme() == "file" {
+        let path = to_path(url)?;
+        let _lock = FileLock::acquire(path.parent().unwrap())?;
+        let mut index = match fs::read_to_string(&path) {
+            Ok(contents) => contents.parse::<Index>()?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Index::default(),
+            Err(e) => { return Err(Box::new(e)); }
+        };
+        if index.entry(&entry.version).is_some() {
+            return Ok(false);
+        }
+        index.entries.push(entry);
+        // Replace atomically, so the index is never seen half written
+        let part = path.with_extension("toml.part");
+        fs::write(&part,index.to_string())?;
+        fs::rename(&part,&path)?;
+        info!("Uploaded {}",url);
+        return Ok(true);
+    }
+    let client = reqwest::blocking::Client::new();
+    for _ in 0..MAX_ATTEMPTS {
+        let response = client.get(url.clone()).send()?;
+        let (mut index,condition) = match response.status() {
+            StatusCode::NOT_FOUND => (Index::default(),(IF_NONE_MATCH,"*".to_string())),
+            s if s.is_success() => {
+                let etag = match response.headers().get(ETAG).and_then(|e| e.to_str().ok()) {
+                    Some(e) => e.to_string(),
+                    None => {
+                        let message = format!("cannot safely update {} (no ETag given)",url);
+                        return Err(Box::new(PublishError{message}));
+                    }
+                };
+                (response.text()?.parse::<Index>()?,(IF_MATCH,etag))
+            }
+            s => {
+                let message = format!("downloading {} ({:?})",url,s);
+                return Err(Box::new(PublishError{message}));
+            }
+        };
+        if index.entry(&entry.version).is_some() {
+            return Ok(false);
+        }
+        index.entries.push(entry.clone());
+        let response = client.put(url.clone()).header(condition.0,condition.1).body(index.to_string()).send()?;
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            info!("{} changed whilst updating, retrying",url);
+            continue;
+        } else if !response.status().is_success() {
+            let message = format!("uploading {} ({:?})",url,response.status());
+            return Err(Box::new(PublishError{message}));
+        }
+        info!("Uploaded {}",url);
+        return Ok(true);
+    }
+    let message = format!("{} changed too often whilst updating",url);
+    Err(Box::new(PublishError{message}))
+}
+
+/// Determine the index entry for a given version of a package which
+/// has already been uploaded.  Its checksum and dependencies are
+/// taken from the package itself.
+fn uploaded_entry(url: &Url, version: &Version) -> Result<IndexEntry,Box<dyn Error>> {
+    let bytes = match Fetcher::default().get(url)? {
+        Some(bytes) => bytes,
+        None => {
+            let message = format!("downloading {} (not found)",url);
+            return Err(Box::new(PublishError{message}));
+        }
+    };
+    let checksum = util::sha256_bytes(&bytes);
+    let dependencies = package::read_dependencies_from(Cursor::new(&bytes),url.as_str()).map_err(|e| e as Box<dyn Error>)?;
+    Ok(IndexEntry{version:version.clone(),checksum,dependencies,yanked:false})
+}
+
+/// Determine the local file for a `file://` URL.
+fn to_path(url: &Url) -> Result<PathBuf,PublishError> {
+    url.to_file_path().map_err(|_| {
+        let message = format!("invalid registry location {}",url);
+        PublishError{message}
+    })
+}
+
+/// Report that a given version of a package is already published.
+fn published(name: &str, version: &Version) -> PublishError {
+    let message = format!("{}-{} has already been published",name,version);
+    PublishError{message}
+}
+
+// ================================================================
+// Publish Error
+// ================================================================
+
+#[derive(Clone)]
+struct PublishError {
+    message: String
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed publishing package: {}",self.message)
+    }
+}
+
+impl fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed publishing package: {}",self.message)
+    }
+}
+
+impl Error for PublishError {}
@@ -1,79 +1,123 @@
+use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use log::info;
-use crate::{init_classpath};
-use crate::config::{Config,Key};
-use crate::jvm;
+use crate::{init_classpath,init_java};
+use crate::config::Config;
 use crate::jvm::{Jvm};
 use crate::{init_registry};
-use crate::build::{DEPENDENCIES, PACKAGE_NAME};
-use crate::platform::whiley::{MAVEN_DEPS, BUILD_WHILEY_TARGET, BUILD_WHILEY_MAIN, MAIN_DEFAULT, TARGET_DEFAULT};
-
-pub const TMP : &'static str = "dependencies";
+use crate::build::Build;
+use crate::platform;
+use crate::platform::whiley;
+use crate::platform::whiley::{BUILD_WHILEY_TARGET, BUILD_WHILEY_MAIN, MAIN_DEFAULT};
+use crate::workspace::Workspace;
 
 // Run command
-pub fn run(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+pub fn run(whileyhome: &Path, package: Option<&str>) -> Result<bool,Box<dyn Error>> {
     // Read build configuration
     let config_file = fs::read_to_string("wy.toml").expect("Error reading build configuration!");
     // Parse configuration
     let config = Config::from_str(config_file.as_str())?;
-    // Extract build information
-    let name = config.get_string(&PACKAGE_NAME)?;
-    let target = config.get_path(&BUILD_WHILEY_TARGET).unwrap_or(PathBuf::from(TARGET_DEFAULT));
-    let main = config.get_string(&BUILD_WHILEY_MAIN).unwrap_or(MAIN_DEFAULT.to_string());
-    let mut whileypath = Vec::new();
-    // FIXME: this should be placed somewhere else, and use a
-    // resolved.
-    for s in config.find_keys(&DEPENDENCIES).unwrap_or(Vec::new()) {
-        let a = [&TMP,s.as_str()];
-        let k = Key::new(&a);
-	let d = config.get_string(&k)?;
-	let mut pb = PathBuf::new();
-	pb.push(whileyhome);
-	pb.push("repository");
-	pb.push(format!("{}-v{}.zip",&s,&d));
-	// FIXME: whileypath should be Vec of PathBuf
-	let arg = pb.into_os_string().into_string().unwrap();
-	whileypath.push(arg);
-    }
     // Initialise platform registry
     let registry = init_registry();
+    // Run a member of a workspace (if applicable)
+    if let Some(ws) = Workspace::from_config(&config,whileyhome,&registry)? {
+        let name = match package {
+            Some(n) => n,
+            None => {
+                let message = "specify which member to run with -p".to_string();
+                return Err(Box::new(RunError{message}));
+            }
+        };
+        let i = match ws.members.iter().position(|m| m.build.name == name) {
+            Some(i) => i,
+            None => {
+                let message = format!("no member \"{}\"",name);
+                return Err(Box::new(RunError{message}));
+            }
+        };
+        // Build workspace so member is up-to-date
+        let whileypaths = match ws.build(whileyhome)? {
+            Some(wps) => wps,
+            None => { return Ok(false); }
+        };
+        let m = &ws.members[i];
+        return execute(whileyhome,&m.config,&m.build,&whileypaths[i]);
+    }
+    // Construct build plan
+    let build = Build::from_str(&config,&env::current_dir()?,whileyhome,&registry)?;
+    if let Some(n) = package {
+        if n != build.name {
+            let message = format!("no package \"{}\" (found {})",n,build.name);
+            return Err(Box::new(RunError{message}));
+        }
+    }
+    // Resolve package dependencies (including transitive ones)
+    let whileypath = build.initialise(whileyhome)?;
+    execute(whileyhome,&config,&build,&whileypath)
+}
+
+/// Execute the main method of a given build using a given whileypath.
+fn execute(whileyhome: &Path, config: &Config, build: &Build, whileypath: &[String]) -> Result<bool,Box<dyn Error>> {
+    // Extract build information
+    let target = config.get_path(&BUILD_WHILEY_TARGET).unwrap_or(PathBuf::from(whiley::target_default(config)));
+    let main = config.get_string(&BUILD_WHILEY_MAIN).unwrap_or(MAIN_DEFAULT.to_string());
     // Initialise classpath as necessary.  This will download Jar
     // files from Maven central (if not already cached).
-    let cp = init_classpath(&whileyhome,MAVEN_DEPS)?;
+    let cp = init_classpath(whileyhome,&build.repositories,&build.layers,&[whiley::compiler(config,whileyhome)?])?;
+    // Find suitable Java runtime
+    let java = init_java(whileyhome,build.java.as_deref(),whiley::JAVA_VERSION)?;
     // Construct JVM runner
-    let jvm = Jvm::new(cp,vec![("WHILEYHOME",&whileyhome)]);
+    // NOTE: the interpreter is part of the whiley platform
+    let options = build.jvm_options("whiley");
+    let jvm = Jvm::new(java,cp,vec![("WHILEYHOME",&whileyhome)]).with_options(&options).in_dir(&build.dir);
     //
     let mut args : Vec<&str> = Vec::new();
     // Class to invoke
     args.push("wyc.Executor");
     // Target name
     args.push("-o");
-    args.push(&name);
+    args.push(&build.name);
     //
     let wyildir = format!("--wyildir={}",target.to_str().unwrap());
     args.push(&wyildir);
     // Whiley path
-    let mut wypath = String::new();
-    if whileypath.len() > 0 {
-        wypath.push_str("--whileypath=");
-        wypath.push_str(whileypath.get(0).unwrap());
-        for e in &whileypath[1..] {
-            wypath.push_str(jvm::classpath_sep());
-            wypath.push_str(e);
-        }
-	args.push(&wypath);
+    let wypath = platform::whileypath_arg(whileypath);
+    if let Some(w) = &wypath {
+        args.push(w);
     }
     // Target method
     args.push(&main);
     // Log Java command
     info!("Executing java {:?}",args);
     // Go!
-    let output = jvm.exec(&args);
+    let status = jvm.exec_interactive(&args)?;
     //
-    print!("{}",output);
-    //
-    Ok(true)
+    Ok(status.success())
+}
+
+// ================================================================
+// Run Error
+// ================================================================
+
+#[derive(Clone)]
+struct RunError {
+    message: String
 }
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed running package: {}",self.message)
+    }
+}
+
+impl fmt::Debug for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed running package: {}",self.message)
+    }
+}
+
+impl Error for RunError {}
@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use log::warn;
+use crate::maven;
+use crate::maven::MavenArtifact;
+use crate::platform;
+use crate::platform::{whiley,javascript,boogie};
+use crate::toolchain;
+use crate::toolchain::{Toolchain,TOOLCHAINS_DIR};
+use crate::util::FileLock;
+use crate::{init_artifacts,init_overrides,init_poms};
+use super::vendor::copy;
+
+/// Operations supported by the toolchain command.
+pub enum ToolchainCommand<'a> {
+    /// List all installed toolchains
+    List,
+    /// Install the toolchain for a given compiler version, optionally
+    /// with given versions of the JavaScript and Boogie backends.
+    Install(&'a str,Option<&'a str>,Option<&'a str>),
+    /// Remove an installed toolchain
+    Remove(&'a str),
+    /// Make an installed toolchain the default
+    Default(&'a str)
+}
+
+// Toolchain command
+pub fn toolchain(whileyhome: &Path, command: ToolchainCommand) -> Result<bool,Box<dyn Error>> {
+    match command {
+        ToolchainCommand::List => list(whileyhome),
+        ToolchainCommand::Install(v,js,boogie) => install(whileyhome,v,js,boogie),
+        ToolchainCommand::Remove(v) => remove(whileyhome,v),
+        ToolchainCommand::Default(v) => set_default(whileyhome,v)
+    }
+}
+
+/// List every installed toolchain along with its tools, identifying
+/// the default.  This is the toolchain used by any package which does
+/// not specify its own compiler version.
+fn list(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+    let toolchains = toolchain::installed(whileyhome)?;
+    let default = toolchain::default_name(whileyhome);
+    if toolchains.is_empty() {
+        println!("No toolchains installed (using built-in {})",whiley::MAVEN_DEPS[0]);
+    }
+    for t in &toolchains {
+        if default.as_ref() == Some(&t.name) {
+            println!("{} (default)",t.name);
+        } else {
+            println!("{}",t.name);
+        }
+        for tool in &t.tools {
+            println!("    {}",tool);
+        }
+    }
+    Ok(true)
+}
+
+/// Install the toolchain for a given compiler version.  This resolves
+/// its tools (and their dependencies), and copies their jars into the
+/// toolchain's directory.  The first toolchain installed becomes the
+/// default.
+fn install(whileyhome: &Path, version: &str, js: Option<&str>, boogie: Option<&str>) -> Result<bool,Box<dyn Error>> {
+    let tools = vec![
+        tool(whiley::MAVEN_DEPS[0],Some(version))?,
+        tool(javascript::MAVEN_DEPS[1],js)?,
+        tool(boogie::MAVEN_DEPS[1],boogie)?
+    ];
+    // Resolve everything needed
+    let artifacts = init_artifacts(whileyhome,&[],&[],&tools)?;
+    let poms = init_poms(whileyhome,&[],&[],&tools)?;
+    // Populate toolchain directory from scratch
+    let _lock = FileLock::acquire(&whileyhome.join(TOOLCHAINS_DIR))?;
+    let dir = Toolchain::dir(whileyhome,version);
+    let staging = Toolchain::staging_dir(whileyhome,version);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let mavenhome = staging.join("maven");
+    fs::create_dir_all(&mavenhome)?;
+    let overrides = init_overrides(whileyhome)?;
+    for (d,jar) in &artifacts {
+        if maven::is_overridden(&overrides,d) {
+            warn!("Not installing {} (overridden by {})",d,jar.display());
+        } else {
+            copy(jar,&mavenhome.join(MavenArtifact::new(d).unwrap().to_jarpath()))?;
+        }
+    }
+    for (path,pom) in &poms {
+        copy(pom,&mavenhome.join(path))?;
+    }
+    let t = Toolchain{name:version.to_string(),tools};
+    t.write(&staging)?;
+    // Replace toolchain directory
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&staging,&dir)?;
+    println!("Installed toolchain {} ({} jar(s))",version,artifacts.len());
+    // Make default (if none already)
+    if toolchain::default_name(whileyhome).is_none() {
+        toolchain::set_default(whileyhome,Some(version))?;
+        println!("Default toolchain is now {}",version);
+    }
+    Ok(true)
+}
+
+/// Remove an installed toolchain.  If this was the default, then
+/// there is subsequently no default.
+fn remove(whileyhome: &Path, version: &str) -> Result<bool,Box<dyn Error>> {
+    check_version(version)?;
+    let _lock = FileLock::acquire(&whileyhome.join(TOOLCHAINS_DIR))?;
+    check_installed(whileyhome,version)?;
+    fs::remove_dir_all(Toolchain::dir(whileyhome,version))?;
+    if toolchain::default_name(whileyhome).as_deref() == Some(version) {
+        toolchain::set_default(whileyhome,None)?;
+    }
+    println!("Removed toolchain {}",version);
+    Ok(true)
+}
+
+/// Make an installed toolchain the default.
+fn set_default(whileyhome: &Path, version: &str) -> Result<bool,Box<dyn Error>> {
+    check_version(version)?;
+    let _lock = FileLock::acquire(&whileyhome.join(TOOLCHAINS_DIR))?;
+    check_installed(whileyhome,version)?;
+    toolchain::set_default(whileyhome,Some(version))?;
+    println!("Default toolchain is now {}",version);
+    Ok(true)
+}
+
+/// Determine the coordinate of a tool, given its built-in coordinate
+/// and (optionally) the version to use instead.
+fn tool(default: &str, version: Option<&str>) -> Result<String,Box<dyn Error>> {
+    match version {
+        Some(v) => {
+            check_version(v)?;
+            match platform::versioned(default,v) {
+                Some(c) => Ok(c),
+                None => Err(Box::new(invalid_version(v)))
+            }
+        }
+        None => Ok(default.to_string())
+    }
+}
+
+/// Check a given version is valid.  Since versions also name
+/// directories within WHILEYHOME, these cannot contain separators or
+/// refer to the current or parent directory.
+fn check_version(version: &str) -> Result<(),ToolchainError> {
+    if version.is_empty() || version == "." || version == ".." || version.contains(['/','\\']) {
+        Err(invalid_version(version))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid_version(version: &str) -> ToolchainError {
+    let message = format!("invalid version \"{}\"",version);
+    ToolchainError{message}
+}
+
+/// Check a toolchain with a given name is installed.
+fn check_installed(whileyhome: &Path, version: &str) -> Result<(),Box<dyn Error>> {
+    if toolchain::installed(whileyhome)?.iter().any(|t| t.name == version) {
+        Ok(())
+    } else {
+        let message = format!("toolchain {} is not installed",version);
+        Err(Box::new(ToolchainError{message}))
+    }
+}
+
+// ================================================================
+// Toolchain Error
+// ================================================================
+
+#[derive(Clone)]
+pub struct ToolchainError {
+    pub message: String
+}
+
+impl fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}",self.message)
+    }
+}
+
+impl fmt::Debug for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}",self.message)
+    }
+}
+
+impl Error for ToolchainError {}
@@ -0,0 +1,170 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use log::{info,warn};
+use crate::config;
+use crate::config::{Config,Key};
+use crate::build;
+use crate::build::{Build,VENDOR_DIRECTORY};
+use crate::maven;
+use crate::maven::MavenArtifact;
+use crate::platform::Instance;
+use crate::platform::whiley;
+use crate::util;
+use crate::workspace::Workspace;
+use crate::{init_artifacts,init_overrides,init_poms,init_registry};
+
+/// Default directory (within the project) into which dependencies
+/// are vendored.
+const VENDOR_DEFAULT : &str = "vendor";
+
+static VENDOR : Key = Key::new(&["vendor"]);
+
+/// Kinds of checksum copied alongside vendored files.
+const CHECKSUMS : &[&str] = &["sha256","sha1"];
+
+// Vendor command
+
+/// Copy every package and jar needed to build the current package
+/// into its vendor directory, and configure the build to use it.  The
+/// vendor directory has the same structure as WHILEYHOME (i.e. it has
+/// `repository` and `maven` subdirectories), and is searched before
+/// WHILEYHOME.  Thus, subsequent builds need neither WHILEYHOME nor
+/// the network.
+pub fn vendor(whileyhome: &Path) -> Result<bool,Box<dyn Error>> {
+    // Read build configuration
+    let config_file = fs::read_to_string("wy.toml")?;
+    // Parse configuration
+    let config = Config::from_str(config_file.as_str())?;
+    // Initialise platform registry
+    let registry = init_registry();
+    if Workspace::from_config(&config,whileyhome,&registry)?.is_some() {
+        let message = "not supported for workspaces".to_string();
+        return Err(Box::new(VendorError{message}));
+    }
+    // Construct build plan
+    let build = Build::from_str(&config,&env::current_dir()?,whileyhome,&registry)?;
+    // Determine vendor directory, checking it can be configured
+    let configured = match config.get_path(&VENDOR_DIRECTORY) {
+        Ok(dir) => Some(dir),
+        Err(config::Error::Invalid(_)) if config.contains(&VENDOR) => {
+            let message = format!("add directory = \"{}\" to the [vendor] section of wy.toml",VENDOR_DEFAULT);
+            return Err(Box::new(VendorError{message}));
+        }
+        Err(config::Error::Invalid(_)) => None,
+        Err(e) => { return Err(Box::new(e)); }
+    };
+    let dir = configured.clone().unwrap_or(PathBuf::from(VENDOR_DEFAULT));
+    // Resolve everything needed (and record it in the lock file)
+    let whileypath = build.initialise(whileyhome)?;
+    // Populate vendor directory from scratch.  This is staged
+    // alongside, since the build may currently use the vendor
+    // directory itself.
+    let staging = dir.with_extension("new");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let repository = staging.join("repository");
+    let mavenhome = staging.join("maven");
+    fs::create_dir_all(&repository)?;
+    fs::create_dir_all(&mavenhome)?;
+    // Copy packages from caches (or layers), but not path
+    // dependencies (which are built from source).
+    for p in &whileypath {
+        if !build::is_built(p) {
+            let p = Path::new(p);
+            copy(p,&repository.join(p.file_name().unwrap()))?;
+        }
+    }
+    // Copy jars along with their POMs
+    let mut deps = vec![whiley::compiler(&config,whileyhome)?];
+    for p in &build.platforms {
+        if let Instance::Java(i) = p {
+            for d in i.dependencies() {
+                if !deps.contains(d) {
+                    deps.push(d.clone());
+                }
+            }
+        }
+    }
+    let overrides = init_overrides(whileyhome)?;
+    for (d,jar) in init_artifacts(whileyhome,&build.repositories,&build.layers,&deps)? {
+        if maven::is_overridden(&overrides,&d) {
+            warn!("Not vendoring {} (overridden by {})",d,jar.display());
+        } else {
+            copy(&jar,&mavenhome.join(MavenArtifact::new(&d).unwrap().to_jarpath()))?;
+        }
+    }
+    for (path,pom) in init_poms(whileyhome,&build.repositories,&build.layers,&deps)? {
+        copy(&pom,&mavenhome.join(path))?;
+    }
+    // Replace vendor directory
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&staging,&dir)?;
+    // Configure build to use vendor directory
+    if configured.is_none() {
+        let mut file = OpenOptions::new().append(true).open("wy.toml")?;
+        write!(file,"\n[vendor]\ndirectory = \"{}\"\n",VENDOR_DEFAULT)?;
+        info!("Added vendor directory to wy.toml");
+    }
+    println!("Vendored {} package(s) and jar(s) into {}",count(&dir)?,dir.display());
+    Ok(true)
+}
+
+/// Copy a given file to a given location (creating its directory as
+/// necessary), along with any checksums recorded for it.
+pub fn copy(file: &Path, to: &Path) -> Result<(),Box<dyn Error>> {
+    fs::create_dir_all(to.parent().unwrap())?;
+    info!("Vendoring {}",file.display());
+    fs::copy(file,to)?;
+    for ext in CHECKSUMS {
+        let f = maven::checksum_path(file,ext);
+        if f.exists() {
+            info!("Vendoring {}",f.display());
+            fs::copy(&f,maven::checksum_path(to,ext))?;
+        }
+    }
+    Ok(())
+}
+
+/// Count the packages and jars within a vendor directory.
+fn count(dir: &Path) -> Result<usize,Box<dyn Error>> {
+    let mut n = 0;
+    for (sub,ext) in [("repository","zip"),("maven","jar")] {
+        for f in util::files_within(&dir.join(sub))? {
+            if f.extension().is_some_and(|x| x == ext) {
+                n += 1;
+            }
+        }
+    }
+    Ok(n)
+}
+
+// ================================================================
+// Vendor Error
+// ================================================================
+
+#[derive(Clone)]
+pub struct VendorError {
+    pub message: String
+}
+
+impl fmt::Display for VendorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed vendoring dependencies: {}",self.message)
+    }
+}
+
+impl fmt::Debug for VendorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed vendoring dependencies: {}",self.message)
+    }
+}
+
+impl Error for VendorError {}
@@ -14,7 +14,8 @@ pub enum Type {
     Bool,
     Int,
     String,
-    StringArray
+    StringArray,
+    VersionReq
 }
 
 impl fmt::Display for Type {
@@ -32,6 +33,9 @@ impl fmt::Display for Type {
             Type::StringArray => {
                 write!(f, "string array")
             }
+            Type::VersionReq => {
+                write!(f, "version requirement")
+            }
         }
     }
 }
@@ -232,6 +236,27 @@ impl Config {
 	Ok(result)
     }
     
+    /// Responsible for setting the string associated with a given
+    /// key, creating any enclosing tables as necessary.
+    pub fn set_string(&mut self, key: &Key, value: &str) -> Result<(),Error> {
+	let n = key.0.len();
+	let mut val = &mut self.toml;
+	// Traverse key creating tables as necessary
+	for k in &key.0[..n-1] {
+	    let table = val.as_table_mut().ok_or(Error::Invalid(key.to_string()))?;
+	    val = table.entry(k.to_string()).or_insert_with(|| Value::Table(toml::value::Table::new()));
+	}
+	// Assign value
+	let table = val.as_table_mut().ok_or(Error::Invalid(key.to_string()))?;
+	table.insert(key.0[n-1].to_string(),Value::String(value.to_string()));
+	Ok(())
+    }
+
+    /// Responsible for checking whether a given key is present.
+    pub fn contains(&self, key: &Key) -> bool {
+	self.get_key(key).is_some()
+    }
+
     /// Responsible for identifying keys contained (directly) within
     /// this key.
     pub fn find_keys(&self, key: &Key) -> Result<Vec<String>,Error> {
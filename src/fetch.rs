@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::{File,OpenOptions};
+use std::io;
+use std::io::{Cursor,IsTerminal,Read,Seek,SeekFrom,Write};
+use std::path::{Path,PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool,AtomicU8,AtomicUsize,Ordering};
+use std::thread;
+use std::time::Duration;
+use log::{info,warn};
+use reqwest;
+use reqwest::{StatusCode,Url};
+use reqwest::header::RANGE;
+
+/// Number of times a transient failure (e.g. a dropped connection) is
+/// retried before giving up.
+const RETRIES : u32 = 3;
+
+/// Delay before the first retry, which doubles after each attempt.
+const BACKOFF : Duration = Duration::from_millis(500);
+
+/// Maximum number of files fetched at the same time.
+pub const MAX_CONCURRENT : usize = 4;
+
+/// Current mode of operation (see `set_mode()`).
+static MODE : AtomicU8 = AtomicU8::new(Mode::Online as u8);
+
+/// Checks a downloaded file before it is committed to the cache.
+pub type Check<'a> = &'a dyn Fn(&Path) -> Result<(),Box<dyn Error + Send + Sync>>;
+
+// ===================================================================
+// Mode
+// ===================================================================
+
+/// Determines whether files may be fetched (e.g. over the network),
+/// or whether only those already cached may be used.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Mode {
+    Online,
+    /// Only cached files may be used.
+    Offline,
+    /// Only cached files may be used, and resolution must agree with
+    /// the lock file.
+    Frozen
+}
+
+impl Mode {
+    /// Check whether fetching files is prohibited.
+    pub fn is_offline(self) -> bool {
+        self != Mode::Online
+    }
+}
+
+/// Set the mode of operation for this process.
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8,Ordering::SeqCst);
+}
+
+/// Get the mode of operation for this process.
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::SeqCst) {
+        0 => Mode::Online,
+        1 => Mode::Offline,
+        _ => Mode::Frozen
+    }
+}
+
+// ===================================================================
+// Transport
+// ===================================================================
+
+/// The contents of a file being fetched by a transport.
+pub struct Body {
+    /// Offset within the file at which this body starts.  This is
+    /// zero when the transport could not resume from the offset
+    /// requested.
+    pub offset: u64,
+    pub reader: Box<dyn Read>
+}
+
+/// Responsible for reading files from a particular kind of location
+/// (e.g. a remote server or the local filesystem).
+pub trait Transport : Send + Sync {
+    /// Determine whether this transport can read a given URL.
+    fn supports(&self, url: &Url) -> bool;
+
+    /// Open the file at a given URL, starting from a given offset
+    /// where possible.  This returns `None` if no such file exists.
+    fn open(&self, url: &Url, offset: u64) -> Result<Option<Body>,FetchError>;
+}
+
+/// Reads files from remote servers via `http://` or `https://`.
+/// Partial downloads are resumed using range requests.
+#[derive(Default)]
+pub struct HttpTransport {
+    client: reqwest::blocking::Client
+}
+
+impl Transport for HttpTransport {
+    fn supports(&self, url: &Url) -> bool {
+        url.scheme() == "http" || url.scheme() == "https"
+    }
+
+    fn open(&self, url: &Url, offset: u64) -> Result<Option<Body>,FetchError> {
+        let mut request = self.client.get(url.clone());
+        if offset > 0 {
+            request = request.header(RANGE,format!("bytes={}-",offset));
+        }
+        let response = request.send().map_err(|e| FetchError::transient(url,&e))?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            Ok(None)
+        } else if status == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+            // Partial download is unusable, so start again
+            self.open(url,0)
+        } else if status == StatusCode::PARTIAL_CONTENT {
+            Ok(Some(Body{offset,reader:Box::new(response)}))
+        } else if status.is_success() {
+            Ok(Some(Body{offset:0,reader:Box::new(response)}))
+        } else if status.is_server_error() {
+            Err(FetchError::transient(url,&status))
+        } else {
+            Err(FetchError::new(url,&status))
+        }
+    }
+}
+
+/// Reads files from the local filesystem via `file://`.
+pub struct FileTransport {}
+
+impl Transport for FileTransport {
+    fn supports(&self, url: &Url) -> bool {
+        url.scheme() == "file"
+    }
+
+    fn open(&self, url: &Url, offset: u64) -> Result<Option<Body>,FetchError> {
+        let path = url.to_file_path().map_err(|_| FetchError::new(url,&"invalid path"))?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut file = File::open(path).map_err(|e| FetchError::new(url,&e))?;
+        let len = file.metadata().map_err(|e| FetchError::new(url,&e))?.len();
+        // Partial download is unusable if longer than the file
+        let offset = if offset > len { 0 } else { offset };
+        file.seek(SeekFrom::Start(offset)).map_err(|e| FetchError::new(url,&e))?;
+        Ok(Some(Body{offset,reader:Box::new(file)}))
+    }
+}
+
+/// Reads files held in memory, which allows fetching to be exercised
+/// without a network (e.g. for testing).
+#[derive(Default)]
+pub struct MemoryTransport {
+    files: HashMap<String,Vec<u8>>
+}
+
+impl MemoryTransport {
+    /// Add a file with given contents at a given URL.
+    pub fn insert(&mut self, url: &str, contents: Vec<u8>) {
+        self.files.insert(url.to_string(),contents);
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn supports(&self, _: &Url) -> bool {
+        true
+    }
+
+    fn open(&self, url: &Url, offset: u64) -> Result<Option<Body>,FetchError> {
+        match self.files.get(url.as_str()) {
+            Some(bytes) => {
+                // Partial download is unusable if longer than the file
+                let start = if offset as usize > bytes.len() { 0 } else { offset as usize };
+                let reader = Box::new(Cursor::new(bytes[start..].to_vec()));
+                Ok(Some(Body{offset:start as u64,reader}))
+            }
+            None => Ok(None)
+        }
+    }
+}
+
+// ===================================================================
+// Fetcher
+// ===================================================================
+
+/// Responsible for fetching files using an appropriate transport.
+/// Transient failures are retried with exponential backoff, and
+/// downloads are written atomically to the cache.
+pub struct Fetcher {
+    transports: Vec<Box<dyn Transport>>,
+    retries: u32,
+    backoff: Duration
+}
+
+impl Default for Fetcher {
+    /// Construct a fetcher supporting `http://`, `https://` and
+    /// `file://` URLs.
+    fn default() -> Self {
+        Fetcher::new(vec![Box::new(HttpTransport::default()),Box::new(FileTransport{})])
+    }
+}
+
+impl Fetcher {
+    /// Construct a fetcher using a given set of transports.  For each
+    /// URL, the first transport which supports it is used.
+    pub fn new(transports: Vec<Box<dyn Transport>>) -> Self {
+        Fetcher{transports,retries:RETRIES,backoff:BACKOFF}
+    }
+
+    /// Fetch the contents of a given URL into memory.  This returns
+    /// `None` if no such file exists.
+    pub fn get(&self, url: &Url) -> Result<Option<Vec<u8>>,FetchError> {
+        let transport = self.transport(url)?;
+        self.retry(url, || {
+            match transport.open(url,0)? {
+                Some(mut body) => {
+                    let mut bytes = Vec::new();
+                    body.reader.read_to_end(&mut bytes).map_err(|e| FetchError::transient(url,&e))?;
+                    Ok(Some(bytes))
+                }
+                None => Ok(None)
+            }
+        })
+    }
+
+    /// Check whether a file exists at a given URL, without reading
+    /// its contents.
+    pub fn exists(&self, url: &Url) -> Result<bool,FetchError> {
+        let transport = self.transport(url)?;
+        self.retry(url, || Ok(transport.open(url,0)?.is_some()))
+    }
+
+    /// Download the contents of a given URL into a given file.  This
+    /// first writes into a temporary file alongside (e.g.
+    /// `wyc-0.10.18.jar.part`), resuming from any partial download
+    /// left by an earlier attempt.  Once complete, this is checked
+    /// using a given function and renamed into place.  Thus, the
+    /// file is either absent or complete.  This returns `false` if
+    /// no such file exists.
+    pub fn download(&self, url: &Url, path: &Path, check: Check) -> Result<bool,Box<dyn Error + Send + Sync>> {
+        let transport = self.transport(url)?;
+        let part = part_path(path);
+        if !self.retry(url, || download_part(transport,url,&part))? {
+            return Ok(false);
+        }
+        if let Err(e) = check(&part) {
+            fs::remove_file(&part)?;
+            return Err(e);
+        }
+        fs::rename(&part,path)?;
+        info!("Downloaded {}",url.as_str());
+        Ok(true)
+    }
+
+    /// Determine the transport to use for a given URL.  No transport
+    /// is available when offline.
+    fn transport(&self, url: &Url) -> Result<&dyn Transport,FetchError> {
+        if mode().is_offline() {
+            return Err(FetchError::new(url,&"offline"));
+        }
+        match self.transports.iter().find(|t| t.supports(url)) {
+            Some(t) => Ok(t.as_ref()),
+            None => Err(FetchError::new(url,&"unsupported URL"))
+        }
+    }
+
+    /// Run a given operation, retrying after any transient failure.
+    fn retry<T,F>(&self, url: &Url, f: F) -> Result<T,FetchError>
+    where F: Fn() -> Result<T,FetchError>
+    {
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if e.transient && attempt < self.retries => {
+                    warn!("Fetching {} failed, retrying in {:?} ({})",url.as_str(),delay,e.message);
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                r => { return r; }
+            }
+        }
+    }
+}
+
+/// Download a given URL into a given temporary file, resuming from
+/// its current length where possible.
+fn download_part(transport: &dyn Transport, url: &Url, part: &Path) -> Result<bool,FetchError> {
+    let offset = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    let mut body = match transport.open(url,offset)? {
+        Some(b) => b,
+        None => { return Ok(false); }
+    };
+    let file = if offset > 0 && body.offset == offset {
+        info!("Resuming {} from byte {}",url.as_str(),offset);
+        OpenOptions::new().append(true).open(part)
+    } else {
+        File::create(part)
+    };
+    let mut file = file.map_err(|e| FetchError::new(url,&e))?;
+    io::copy(&mut body.reader,&mut file).map_err(|e| FetchError::transient(url,&e))?;
+    Ok(true)
+}
+
+/// Determine the temporary file used whilst downloading a given file.
+fn part_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".part");
+    PathBuf::from(p)
+}
+
+// ===================================================================
+// Parallel Fetching
+// ===================================================================
+
+/// Apply a given (fetching) function to each of a given set of items
+/// concurrently, using at most `MAX_CONCURRENT` threads.  A combined
+/// progress display is shown as items complete.  Should any item
+/// fail, no further items are started though those in flight are
+/// allowed to finish.  Since downloads are committed atomically, the
+/// cache remains consistent regardless.  The results are returned in
+/// the order of the items given, or the first error encountered
+/// (which is why errors must be safe to pass between threads).
+pub fn fetch_all<I,T,F>(items: &[I], f: F) -> Result<Vec<T>,Box<dyn Error + Send + Sync>>
+where I: fmt::Display + Sync, T: Send, F: Fn(&I) -> Result<T,Box<dyn Error + Send + Sync>> + Sync
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let progress = Progress::new(items.len());
+    let results = Mutex::new(items.iter().map(|_| None).collect::<Vec<_>>());
+    let worker = || {
+        loop {
+            let i = next.fetch_add(1,Ordering::SeqCst);
+            if i >= items.len() || failed.load(Ordering::SeqCst) {
+                break;
+            }
+            let r = f(&items[i]);
+            if r.is_err() {
+                failed.store(true,Ordering::SeqCst);
+            }
+            progress.tick(&items[i]);
+            results.lock().unwrap()[i] = Some(r);
+        }
+    };
+    thread::scope(|s| {
+        for _ in 0..MAX_CONCURRENT.min(items.len()) {
+            s.spawn(worker);
+        }
+    });
+    progress.finish();
+    // Done
+    let mut values = Vec::new();
+    for r in results.into_inner().unwrap() {
+        match r {
+            Some(Ok(v)) => values.push(v),
+            Some(Err(e)) => { return Err(e); }
+            // Not started because an earlier item failed
+            None => {}
+        }
+    }
+    Ok(values)
+}
+
+/// Reports the combined progress of a number of concurrent fetches
+/// on a single line.  This is only shown when writing to a terminal.
+struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    visible: bool
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        let visible = total > 1 && io::stderr().is_terminal();
+        Progress{total,done:AtomicUsize::new(0),visible}
+    }
+
+    /// Record that a given item has completed.
+    fn tick(&self, item: &dyn fmt::Display) {
+        let done = self.done.fetch_add(1,Ordering::SeqCst) + 1;
+        if self.visible {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr,"\r\x1b[KFetched [{}/{}] {}",done,self.total,item);
+            let _ = stderr.flush();
+        }
+    }
+
+    fn finish(&self) {
+        if self.visible {
+            eprintln!();
+        }
+    }
+}
+
+// ===================================================================
+// Fetch Error
+// ===================================================================
+
+/// Indicates a file could not be fetched from a given URL.  Transient
+/// failures (e.g. dropped connections) may succeed if retried.
+#[derive(Clone)]
+pub struct FetchError {
+    pub url: String,
+    pub message: String,
+    pub transient: bool
+}
+
+impl FetchError {
+    pub fn new(url: &Url, message: &dyn fmt::Display) -> Self {
+        FetchError{url:url.to_string(),message:message.to_string(),transient:false}
+    }
+
+    pub fn transient(url: &Url, message: &dyn fmt::Display) -> Self {
+        FetchError{url:url.to_string(),message:message.to_string(),transient:true}
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed fetching {} ({})",self.url,self.message)
+    }
+}
+
+impl fmt::Debug for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed fetching {} ({})",self.url,self.message)
+    }
+}
+
+impl Error for FetchError {}
+
+// ===================================================================
+// Cache Error
+// ===================================================================
+
+/// Indicates a file needed whilst offline is not in the local cache.
+#[derive(Clone)]
+pub struct CacheError {
+    pub name: String
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not in cache (offline)",self.name)
+    }
+}
+
+impl fmt::Debug for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not in cache (offline)",self.name)
+    }
+}
+
+impl Error for CacheError {}
+
+// ===================================================================
+// Resolution Error
+// ===================================================================
+
+/// Indicates an artifact (e.g. a package or jar file) could not be
+/// resolved.
+#[derive(Clone)]
+pub struct ResolutionError {
+    pub name: String
+}
+
+impl fmt::Display for ResolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed resolving {}",self.name)
+    }
+}
+
+impl fmt::Debug for ResolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed resolving {}",self.name)
+    }
+}
+
+impl Error for ResolutionError {}
+
+// ===================================================================
+// Tests
+// ===================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Instant;
+    use crate::util;
+
+    const URL : &str = "https://example.org/wyc-0.10.18.jar";
+
+    const CONTENTS : &[u8] = b"0123456789abcdef";
+
+    /// A transport which fails (transiently) a given number of times
+    /// before succeeding.
+    struct FlakyTransport {
+        failures: AtomicU32,
+        inner: MemoryTransport
+    }
+
+    impl Transport for FlakyTransport {
+        fn supports(&self, url: &Url) -> bool {
+            self.inner.supports(url)
+        }
+
+        fn open(&self, url: &Url, offset: u64) -> Result<Option<Body>,FetchError> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1,Ordering::SeqCst);
+                return Err(FetchError::transient(url,&"connection reset"));
+            }
+            self.inner.open(url,offset)
+        }
+    }
+
+    /// Construct a fetcher which fails a given number of times before
+    /// serving `CONTENTS` at `URL`.
+    fn fetcher(failures: u32) -> Fetcher {
+        let mut inner = MemoryTransport::default();
+        inner.insert(URL,CONTENTS.to_vec());
+        let transport = FlakyTransport{failures:AtomicU32::new(failures),inner};
+        let mut fetcher = Fetcher::new(vec![Box::new(transport)]);
+        fetcher.backoff = Duration::from_millis(10);
+        fetcher
+    }
+
+    fn url() -> Url {
+        Url::parse(URL).unwrap()
+    }
+
+    #[test]
+    fn get_file() {
+        let f = fetcher(0);
+        assert_eq!(f.get(&url()).unwrap().as_deref(),Some(CONTENTS));
+        assert_eq!(f.get(&Url::parse("https://example.org/missing").unwrap()).unwrap(),None);
+        assert!(f.exists(&url()).unwrap());
+    }
+
+    #[test]
+    fn retry_with_backoff() {
+        let f = fetcher(2);
+        let start = Instant::now();
+        assert_eq!(f.get(&url()).unwrap().as_deref(),Some(CONTENTS));
+        // Waits 10ms then 20ms
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn retry_gives_up() {
+        let f = fetcher(RETRIES+1);
+        let err = f.get(&url()).unwrap_err();
+        assert!(err.transient);
+        assert_eq!(err.message,"connection reset");
+        // Succeeds once the failures are exhausted
+        assert!(f.get(&url()).is_ok());
+    }
+
+    #[test]
+    fn permanent_failure() {
+        let f = Fetcher::new(vec![Box::new(FileTransport{})]);
+        let err = f.get(&url()).unwrap_err();
+        assert!(!err.transient);
+        assert_eq!(err.message,"unsupported URL");
+    }
+
+    #[test]
+    fn atomic_download() {
+        let dir = util::test_dir("atomic_download");
+        let path = dir.join("wyc-0.10.18.jar");
+        let part = part_path(&path);
+        let check = |p: &Path| -> Result<(),Box<dyn Error + Send + Sync>> {
+            // Nothing is committed until checked
+            assert_eq!(p,part.as_path());
+            assert!(!path.exists());
+            Ok(())
+        };
+        assert!(fetcher(1).download(&url(),&path,&check).unwrap());
+        assert_eq!(fs::read(&path).unwrap(),CONTENTS);
+        assert!(!part.exists());
+    }
+
+    #[test]
+    fn download_missing() {
+        let dir = util::test_dir("download_missing");
+        let path = dir.join("missing.jar");
+        let missing = Url::parse("https://example.org/missing.jar").unwrap();
+        assert!(!fetcher(0).download(&missing,&path,&|_| Ok(())).unwrap());
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[test]
+    fn resume_download() {
+        let dir = util::test_dir("resume_download");
+        let path = dir.join("wyc-0.10.18.jar");
+        // NOTE: differs from the original, showing it is kept
+        fs::write(part_path(&path),b"ABCDEF").unwrap();
+        assert!(fetcher(0).download(&url(),&path,&|_| Ok(())).unwrap());
+        assert_eq!(fs::read(&path).unwrap(),b"ABCDEF6789abcdef");
+    }
+
+    #[test]
+    fn restart_download() {
+        let dir = util::test_dir("restart_download");
+        let path = dir.join("wyc-0.10.18.jar");
+        // Partial download is longer than the file itself
+        fs::write(part_path(&path),b"0123456789abcdefXYZ").unwrap();
+        assert!(fetcher(0).download(&url(),&path,&|_| Ok(())).unwrap());
+        assert_eq!(fs::read(&path).unwrap(),CONTENTS);
+    }
+
+    #[test]
+    fn file_transport_resume() {
+        let dir = util::test_dir("file_transport_resume");
+        let source = dir.join("source.jar");
+        fs::write(&source,CONTENTS).unwrap();
+        let url = Url::from_file_path(&source).unwrap();
+        let path = dir.join("wyc-0.10.18.jar");
+        let f = Fetcher::new(vec![Box::new(FileTransport{})]);
+        fs::write(part_path(&path),b"ABCDEF").unwrap();
+        assert!(f.download(&url,&path,&|_| Ok(())).unwrap());
+        assert_eq!(fs::read(&path).unwrap(),b"ABCDEF6789abcdef");
+        fs::write(part_path(&path),b"0123456789abcdefXYZ").unwrap();
+        assert!(f.download(&url,&path,&|_| Ok(())).unwrap());
+        assert_eq!(fs::read(&path).unwrap(),CONTENTS);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let dir = util::test_dir("checksum_mismatch");
+        let path = dir.join("wyc-0.10.18.jar");
+        let check = |_: &Path| -> Result<(),Box<dyn Error + Send + Sync>> {
+            Err(Box::new(ResolutionError{name:"wyc-0.10.18.jar".to_string()}))
+        };
+        let err = fetcher(0).download(&url(),&path,&check).unwrap_err();
+        assert_eq!(err.to_string(),"failed resolving wyc-0.10.18.jar");
+        // Neither the file nor the partial download remain
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[test]
+    fn fetch_all_in_order() {
+        let items = vec![3,1,2,5,4];
+        let results = fetch_all(&items,|i| Ok(i * 10)).unwrap();
+        assert_eq!(results,vec![30,10,20,50,40]);
+    }
+
+    #[test]
+    fn fetch_all_failure() {
+        let items = vec![1,2,3];
+        let err = fetch_all(&items,|i| {
+            if *i == 2 {
+                Err(Box::new(CacheError{name:"two".to_string()}) as Box<dyn Error + Send + Sync>)
+            } else {
+                Ok(*i)
+            }
+        }).unwrap_err();
+        // The original error is returned
+        assert!(err.downcast_ref::<CacheError>().is_some());
+        assert_eq!(err.to_string(),"two is not in cache (offline)");
+    }
+}
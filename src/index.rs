@@ -0,0 +1,254 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use reqwest::Url;
+use semver::Version;
+use toml;
+use toml::Value;
+use toml::value::{Array,Table};
+use crate::config;
+use crate::config::Type;
+use crate::package::Dependency;
+
+/// Name of the index file for each package.  This lives in the
+/// package's directory within the registry, alongside the
+/// directories holding each version (e.g. `std/index.toml` next to
+/// `std/0.3.2/std-v0.3.2.zip`).
+pub const INDEX_FILE : &str = "index.toml";
+
+/// Determine the URL of the index file for a given package within a
+/// given registry.
+pub fn to_index_url(base: &Url, name: &str) -> Url {
+    let n = format!("{}/{}",name,INDEX_FILE);
+    base.join(&n).unwrap()
+}
+
+/// Parse the root of a registry, which is either a URL (e.g.
+/// `https://` or `file://`) or a path to a local directory.  The
+/// result always ends with a `/` so that relative URLs can be joined
+/// onto it.
+pub fn parse_root(root: &str) -> Option<Url> {
+    parse_root_from(&std::env::current_dir().ok()?,root)
+}
+
+/// Parse the root of a registry (or repository), where a relative
+/// path is taken relative to a given directory.
+pub fn parse_root_from(dir: &Path, root: &str) -> Option<Url> {
+    let mut url = match Url::parse(root) {
+        // NOTE: single-letter schemes are Windows drive letters
+        Ok(u) if u.scheme().len() > 1 => u,
+        _ => {
+            let path = dir.join(root);
+            let path = path.canonicalize().unwrap_or(path);
+            Url::from_directory_path(path).ok()?
+        }
+    };
+    if !url.path().ends_with('/') {
+        let p = format!("{}/",url.path());
+        url.set_path(&p);
+    }
+    Some(url)
+}
+
+// ===================================================================
+// Index Entry
+// ===================================================================
+
+/// Describes a single published version of a package.
+#[derive(Clone,Debug,PartialEq)]
+pub struct IndexEntry {
+    pub version: Version,
+    /// SHA-256 of the package's zip file
+    pub checksum: String,
+    /// Dependencies of this version of the package
+    pub dependencies: Vec<Dependency>,
+    /// Indicates this version should no longer be selected during
+    /// resolution.
+    pub yanked: bool
+}
+
+// ===================================================================
+// Index
+// ===================================================================
+
+/// The index for a given package, which lists every version which
+/// has been published.  For example:
+///
+/// ```toml
+/// [[version]]
+/// version = "0.3.2"
+/// checksum = "0b361fc2..."
+/// yanked = false
+///
+/// [version.dependencies]
+/// std = "^0.3"
+/// ```
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>
+}
+
+impl Index {
+    /// Get the entry for a given version (if it exists).
+    pub fn entry(&self, version: &Version) -> Option<&IndexEntry> {
+        self.entries.iter().find(|e| &e.version == version)
+    }
+
+    /// Determine all versions which have not been yanked.
+    pub fn versions(&self) -> Vec<Version> {
+        self.entries.iter().filter(|e| !e.yanked).map(|e| e.version.clone()).collect()
+    }
+}
+
+impl FromStr for Index {
+    type Err = config::Error;
+
+    /// Parse a given string into a package index.
+    fn from_str(contents: &str) -> Result<Index,config::Error> {
+        let toml: Value = toml::from_str(contents)?;
+        let mut entries = Vec::new();
+        //
+        if let Some(v) = toml.get("version") {
+            let arr = v.as_array().ok_or_else(|| config::Error::Invalid("version".to_string()))?;
+            for e in arr {
+                entries.push(parse_entry(e)?);
+            }
+        }
+        Ok(Index{entries})
+    }
+}
+
+/// Parse a single entry in a package index.
+fn parse_entry(entry: &Value) -> Result<IndexEntry,config::Error> {
+    let version = entry.get("version").and_then(Value::as_str);
+    let version = version.and_then(|v| Version::parse(v).ok());
+    let version = version.ok_or_else(|| config::Error::Expected(Type::String,"version.version".to_string()))?;
+    let checksum = entry.get("checksum").and_then(Value::as_str);
+    let checksum = checksum.ok_or_else(|| config::Error::Expected(Type::String,"version.checksum".to_string()))?;
+    let yanked = match entry.get("yanked") {
+        None => false,
+        Some(v) => v.as_bool().ok_or_else(|| config::Error::Expected(Type::Bool,"version.yanked".to_string()))?
+    };
+    let mut dependencies = Vec::new();
+    if let Some(deps) = entry.get("dependencies") {
+        let deps = deps.as_table().ok_or_else(|| config::Error::Invalid("version.dependencies".to_string()))?;
+        for (k,v) in deps {
+            let key = format!("version.dependencies.{}",k);
+            let req = v.as_str().ok_or_else(|| config::Error::Expected(Type::VersionReq,key.clone()))?;
+            let dep = Dependency::parse(k.clone(),req).map_err(|_| config::Error::Expected(Type::VersionReq,key))?;
+            dependencies.push(dep);
+        }
+    }
+    Ok(IndexEntry{version,checksum:checksum.to_string(),dependencies,yanked})
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut entries = Array::new();
+        for e in &self.entries {
+            let mut deps = Table::new();
+            for d in &e.dependencies {
+                deps.insert(d.name().to_string(),Value::String(d.requirement().to_string()));
+            }
+            let mut t = Table::new();
+            t.insert("version".to_string(),Value::String(e.version.to_string()));
+            t.insert("checksum".to_string(),Value::String(e.checksum.clone()));
+            t.insert("yanked".to_string(),Value::Boolean(e.yanked));
+            t.insert("dependencies".to_string(),Value::Table(deps));
+            entries.push(Value::Table(t));
+        }
+        let mut root = Table::new();
+        root.insert("version".to_string(),Value::Array(entries));
+        //
+        let body = toml::to_string(&Value::Table(root)).map_err(|_| fmt::Error)?;
+        write!(f,"{}",body)
+    }
+}
+
+// ===================================================================
+// Tests
+// ===================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX : &str = r#"
+[[version]]
+version = "0.3.1"
+checksum = "aa"
+yanked = true
+
+[[version]]
+version = "0.3.2"
+checksum = "bb"
+
+[version.dependencies]
+std = "^0.3"
+math = ">=0.1, <0.3"
+"#;
+
+    fn version(v: &str) -> Version {
+        Version::parse(v).unwrap()
+    }
+
+    #[test]
+    fn parse_index() {
+        let index : Index = INDEX.parse().unwrap();
+        assert_eq!(index.entries.len(),2);
+        let e = index.entry(&version("0.3.2")).unwrap();
+        assert_eq!(e.checksum,"bb");
+        assert!(!e.yanked);
+        let mut deps : Vec<String> = e.dependencies.iter().map(|d| d.to_string()).collect();
+        deps.sort();
+        assert_eq!(deps,vec!["math >=0.1, <0.3","std ^0.3"]);
+        assert!(index.entry(&version("0.4.0")).is_none());
+    }
+
+    #[test]
+    fn parse_empty_index() {
+        assert_eq!("".parse::<Index>().unwrap(),Index::default());
+    }
+
+    #[test]
+    fn parse_invalid_index() {
+        assert!("[[version]]\nversion = \"x\"\nchecksum = \"aa\"\n".parse::<Index>().is_err());
+        assert!("[[version]]\nversion = \"0.1.0\"\n".parse::<Index>().is_err());
+        assert!("[[version]]\nversion = \"0.1.0\"\nchecksum = \"aa\"\nyanked = \"no\"\n".parse::<Index>().is_err());
+        assert!("[[version]]\nversion = \"0.1.0\"\nchecksum = \"aa\"\n[version.dependencies]\nstd = \"^^\"\n".parse::<Index>().is_err());
+    }
+
+    #[test]
+    fn yanked_versions() {
+        let index : Index = INDEX.parse().unwrap();
+        // Yanked versions remain in the index, but are never offered
+        assert!(index.entry(&version("0.3.1")).unwrap().yanked);
+        assert_eq!(index.versions(),vec![version("0.3.2")]);
+    }
+
+    #[test]
+    fn round_trip_index() {
+        let index : Index = INDEX.parse().unwrap();
+        assert_eq!(index.to_string().parse::<Index>().unwrap(),index);
+    }
+
+    #[test]
+    fn parse_url_roots() {
+        let dir = Path::new("/tmp");
+        let url = parse_root_from(dir,"https://example.org/registry").unwrap();
+        assert_eq!(url.as_str(),"https://example.org/registry/");
+        let url = parse_root_from(dir,"file:///srv/registry/").unwrap();
+        assert_eq!(url.as_str(),"file:///srv/registry/");
+        assert_eq!(to_index_url(&url,"std").as_str(),"file:///srv/registry/std/index.toml");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn parse_path_roots() {
+        let dir = Path::new("/nonexistent");
+        let url = parse_root_from(dir,"registry").unwrap();
+        assert_eq!(url.as_str(),"file:///nonexistent/registry/");
+        let url = parse_root_from(dir,"/srv/registry").unwrap();
+        assert_eq!(url.as_str(),"file:///srv/registry/");
+    }
+}
@@ -1,19 +1,152 @@
+use std::env;
+use std::error::Error;
 use std::ffi::OsStr;
-use std::path::Path;
-use std::process::Command;
+use std::fmt;
+use std::io;
+use std::io::{BufRead,BufReader,Read};
+use std::path::{Path,PathBuf};
+use std::process::{Child,Command,ExitStatus,Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64,Ordering};
+use std::thread;
+use std::time::{Duration,Instant};
+use log::info;
+use crate::config;
+use crate::config::{Config,Key,Type};
+
+/// Environment variable overriding the maximum heap size of the JVM
+/// (e.g. `4g`).
+pub const HEAP_VAR : &str = "WHILEY_JVM_HEAP";
+
+/// Environment variable giving additional (whitespace separated)
+/// arguments for the JVM.
+pub const ARGS_VAR : &str = "WHILEY_JVM_ARGS";
+
+/// Exit status of wy when interrupted (i.e. `128 + SIGINT`, as for a
+/// shell).
+pub const INTERRUPTED : i32 = 130;
+
+/// How often to check whether a JVM with a timeout has finished.
+const POLL_INTERVAL : Duration = Duration::from_millis(50);
+
+/// Timeout (in seconds) given on the command-line, which overrides
+/// any configured.  This is `u64::MAX` when none was given.
+static TIMEOUT : AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// JVMs currently running, which must be terminated if wy is
+/// interrupted.  Each is identified by its process id, along with
+/// whether it leads its own process group.
+static RUNNING : Mutex<Vec<(u32,bool)>> = Mutex::new(Vec::new());
+
+/// Set the timeout (in seconds) for every JVM run, overriding any
+/// configured.  A timeout of zero means there is none.
+pub fn set_timeout(seconds: u64) {
+    TIMEOUT.store(seconds,Ordering::SeqCst);
+}
+
+fn timeout() -> Option<u64> {
+    match TIMEOUT.load(Ordering::SeqCst) {
+        u64::MAX => None,
+        t => Some(t)
+    }
+}
+
+/// Install a handler such that, when wy is interrupted (e.g. by
+/// Ctrl-C), any running JVMs are terminated (along with anything they
+/// started) before exiting with status `INTERRUPTED`.  Otherwise, a
+/// JVM could outlive wy.
+pub fn init_interrupts() -> Result<(),ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        // NOTE: this holds the lock until exiting, such that those
+        // waiting on a terminated JVM cannot carry on.
+        let running = RUNNING.lock().unwrap();
+        for (pid,group) in running.iter() {
+            terminate(*pid,*group);
+        }
+        eprintln!("Interrupted");
+        std::process::exit(INTERRUPTED);
+    })
+}
 
 pub struct Jvm<T: AsRef<Path>, K: AsRef<OsStr>, V: AsRef<OsStr>> {
+    /// Java runtime to use (see `find_java()`)
+    java: PathBuf,
+    /// Arguments for the JVM itself (see `JvmOptions`)
+    options: Vec<String>,
+    /// Wall-clock time after which the JVM is killed (if any)
+    timeout: Option<Duration>,
+    /// Directory in which the JVM is run (if not the current one)
+    dir: Option<PathBuf>,
     classpath: Vec<T>,
     env: Vec<(K,V)>
 }
 
 impl<T: AsRef<Path>, K: AsRef<OsStr>, V: AsRef<OsStr>> Jvm<T,K,V> {
-    pub fn new(classpath: Vec<T>, env: Vec<(K,V)>) -> Self {
-	Jvm{classpath,env}
+    pub fn new(java: PathBuf, classpath: Vec<T>, env: Vec<(K,V)>) -> Self {
+	Jvm{java,options:Vec::new(),timeout:None,dir:None,classpath,env}
+    }
+
+    /// Pass a given set of options to the JVM (e.g. `-Xmx4g`).
+    /// These precede the classpath.  Any timeout given on the
+    /// command-line takes precedence over that given here.
+    pub fn with_options(mut self, options: &JvmOptions) -> Self {
+	self.options = options.to_args();
+	self.timeout = timeout().or(options.timeout).filter(|t| *t > 0).map(Duration::from_secs);
+	self
+    }
+
+    /// Run the JVM in a given directory (e.g. that of the package
+    /// being built), against which any relative arguments are then
+    /// resolved.
+    pub fn in_dir(mut self, dir: &Path) -> Self {
+	self.dir = Some(dir.to_path_buf());
+	self
+    }
+
+    /// Run the JVM with a given set of arguments (e.g. the main class
+    /// followed by its arguments), waiting for it to finish.  Its
+    /// output is captured, though each line is also passed to a given
+    /// function as it is produced (e.g. to report progress).
+    pub fn exec<F>(self, args: &[&str], on_line: F) -> Result<JvmOutput,Box<dyn Error>>
+    where F: Fn(&str) + Sync
+    {
+	let timeout = self.timeout;
+	let mut cmd = self.command(args);
+	cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+	// Run in its own process group, so that anything it starts
+	// can be terminated with it.
+	own_group(&mut cmd);
+	let mut child = spawn(&mut cmd,true)?;
+	let out = child.stdout.take().unwrap();
+	let err = child.stderr.take().unwrap();
+	// Read both streams at once, since either may fill up
+	let (status,stdout,stderr) = thread::scope(|s| {
+	    let hout = s.spawn(|| read_lines(out,&on_line));
+	    let herr = s.spawn(|| read_lines(err,&on_line));
+	    let status = wait(&mut child,true,timeout);
+	    (status,hout.join().unwrap(),herr.join().unwrap())
+	});
+	// Done
+	Ok(JvmOutput{status:status?,stdout:stdout?,stderr:stderr?})
     }
 
-    pub fn exec(self, _args: &[&str]) -> String {
-	let mut args = Vec::new();
+    /// Run the JVM with a given set of arguments, connecting it
+    /// directly to the terminal.  That is, it reads from stdin and its
+    /// output appears as it is produced, such that interactive
+    /// programs can be run.  NOTE: this remains in the process group
+    /// of wy, since otherwise it could not read from the terminal.
+    /// Hence, it is terminated along with its descendants instead.
+    pub fn exec_interactive(self, args: &[&str]) -> Result<ExitStatus,Box<dyn Error>> {
+	let timeout = self.timeout;
+	let mut cmd = self.command(args);
+	cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+	let mut child = spawn(&mut cmd,false)?;
+	wait(&mut child,false,timeout)
+    }
+
+    /// Construct the command to run the JVM with a given set of
+    /// arguments.
+    fn command(self, args: &[&str]) -> Command {
 	// Configure classpath
 	let mut cp = String::new();
 	//
@@ -23,22 +156,213 @@ impl<T: AsRef<Path>, K: AsRef<OsStr>, V: AsRef<OsStr>> Jvm<T,K,V> {
 	    }
 	    cp.push_str(c.as_ref().to_str().unwrap());
 	}
-	//
-	args.push("-cp");
-	args.push(cp.as_str());
-	// Configure launcher
-	args.extend_from_slice(_args);
-	// Run Java!
-	let output = Command::new("java")
-	    .args(args)
-	    .envs(self.env)
-	    .output()
-	    .expect("Java is not installed");
-	// Merge stdout/stderr together
-	let mut r = String::from_utf8(output.stdout).unwrap();
-	r.push_str(&String::from_utf8(output.stderr).unwrap());
-	// Done
-	r
+	// Construct Java command, with the options preceding the
+	// launcher (i.e. main class and its arguments)
+	let mut cmd = Command::new(&self.java);
+	cmd.args(&self.options).arg("-cp").arg(cp).args(args).envs(self.env);
+	if let Some(dir) = &self.dir {
+	    cmd.current_dir(dir);
+	}
+	cmd
+    }
+}
+
+/// Start a given JVM, recording it as running (see `RUNNING`).
+/// NOTE: this holds the lock whilst spawning, such that an interrupt
+/// cannot be handled between the JVM starting and being recorded.
+fn spawn(cmd: &mut Command, group: bool) -> io::Result<Child> {
+    let mut running = RUNNING.lock().unwrap();
+    let child = cmd.spawn()?;
+    running.push((child.id(),group));
+    Ok(child)
+}
+
+/// Wait for a given JVM to finish, terminating it if it runs for
+/// longer than a given timeout (if any).  Whilst running, it is
+/// terminated if wy is interrupted.
+fn wait(child: &mut Child, group: bool, timeout: Option<Duration>) -> Result<ExitStatus,Box<dyn Error>> {
+    let pid = child.id();
+    let start = Instant::now();
+    let r = loop {
+	match (timeout,child.try_wait()) {
+	    (_,Ok(Some(status))) => break Ok(status),
+	    (_,Err(e)) => break Err(Box::new(e) as Box<dyn Error>),
+	    (None,Ok(None)) => break child.wait().map_err(|e| Box::new(e) as Box<dyn Error>),
+	    (Some(t),Ok(None)) if start.elapsed() >= t => {
+		terminate(pid,group);
+		let _ = child.wait();
+		break Err(Box::new(TimeoutError{seconds:t.as_secs()}));
+	    }
+	    (Some(_),Ok(None)) => thread::sleep(POLL_INTERVAL)
+	}
+    };
+    RUNNING.lock().unwrap().retain(|(p,_)| *p != pid);
+    r
+}
+
+#[cfg(unix)]
+fn own_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn own_group(_: &mut Command) {
+}
+
+/// Forcibly terminate a given process, along with its process group
+/// (if it leads one) or, otherwise, its descendants.
+#[cfg(unix)]
+fn terminate(pid: u32, group: bool) {
+    let pid = pid as libc::pid_t;
+    if group {
+	unsafe { libc::killpg(pid,libc::SIGKILL); }
+    } else {
+	for p in stop_tree(pid) {
+	    unsafe { libc::kill(p,libc::SIGKILL); }
+	}
+    }
+}
+
+/// Stop a given process and all of its descendants, returning them.
+/// Since stopped processes cannot start others, the tree cannot
+/// change once this returns.
+#[cfg(unix)]
+fn stop_tree(pid: libc::pid_t) -> Vec<libc::pid_t> {
+    let mut tree = vec![pid];
+    unsafe { libc::kill(pid,libc::SIGSTOP); }
+    loop {
+	let children : Vec<libc::pid_t> = parents().into_iter()
+	    .filter(|(p,pp)| tree.contains(pp) && !tree.contains(p))
+	    .map(|(p,_)| p).collect();
+	if children.is_empty() {
+	    return tree;
+	}
+	for c in children {
+	    unsafe { libc::kill(c,libc::SIGSTOP); }
+	    tree.push(c);
+	}
+    }
+}
+
+/// Determine the parent of every running process, as pairs of
+/// process ids.  This uses `/proc` where available, and `ps`
+/// otherwise (e.g. on macOS).
+#[cfg(unix)]
+fn parents() -> Vec<(libc::pid_t,libc::pid_t)> {
+    let mut parents = Vec::new();
+    if let Ok(entries) = std::fs::read_dir("/proc") {
+	for e in entries.flatten() {
+	    // NOTE: the parent follows the command name, which is
+	    // bracketed and may itself contain spaces or brackets.
+	    let stat = match std::fs::read_to_string(e.path().join("stat")) {
+		Ok(s) => s,
+		Err(_) => { continue; }
+	    };
+	    let fields : Vec<&str> = stat.rsplit_once(')').map(|(_,r)| r.split_whitespace().collect()).unwrap_or_default();
+	    let pid = e.file_name().to_str().and_then(|p| p.parse().ok());
+	    let ppid = fields.get(1).and_then(|p| p.parse().ok());
+	    if let (Some(p),Some(pp)) = (pid,ppid) {
+		parents.push((p,pp));
+	    }
+	}
+    } else {
+	// NOTE: without this, descendants of the process cannot be
+	// found and may then outlive it.
+	let output = match Command::new("ps").args(["-A","-o","pid=","-o","ppid="]).output() {
+	    Ok(o) if o.status.success() => o,
+	    Ok(o) => {
+		log::warn!("Listing processes failed (ps {})",o.status);
+		return parents;
+	    }
+	    Err(e) => {
+		log::warn!("Listing processes failed ({})",e);
+		return parents;
+	    }
+	};
+	for line in String::from_utf8_lossy(&output.stdout).lines() {
+	    let fields : Vec<&str> = line.split_whitespace().collect();
+	    let pid = fields.first().and_then(|p| p.parse().ok());
+	    let ppid = fields.get(1).and_then(|p| p.parse().ok());
+	    if let (Some(p),Some(pp)) = (pid,ppid) {
+		parents.push((p,pp));
+	    }
+	}
+    }
+    parents
+}
+
+/// Forcibly terminate a given process, along with anything it
+/// started.
+#[cfg(not(unix))]
+fn terminate(pid: u32, _: bool) {
+    let _ = Command::new("taskkill").args(["/F","/T","/PID",&pid.to_string()]).status();
+}
+
+/// Read a given stream line by line until it is closed, passing each
+/// line to a given function.  Everything read is returned.
+fn read_lines<R: Read, F: Fn(&str)>(stream: R, on_line: &F) -> io::Result<String> {
+    let mut reader = BufReader::new(stream);
+    let mut contents = String::new();
+    let mut bytes = Vec::new();
+    while reader.read_until(b'\n',&mut bytes)? > 0 {
+	let line = String::from_utf8_lossy(&bytes);
+	on_line(line.trim_end_matches(['\r','\n']));
+	contents.push_str(&line);
+	bytes.clear();
+    }
+    Ok(contents)
+}
+
+// ===================================================================
+// Output
+// ===================================================================
+
+/// Markers in the error output of the JVM indicating it crashed,
+/// rather than the program it was running reporting a failure.
+const CRASHES : &[&str] = &[
+    "Exception in thread ",
+    "# A fatal error has been detected by the Java Runtime Environment",
+    "Error occurred during initialization of VM",
+    "Error: Could not find or load main class",
+    "Error: Unable to initialize main class"
+];
+
+/// The result of running the JVM.
+pub struct JvmOutput {
+    /// Exit status of the JVM
+    pub status: ExitStatus,
+    /// Everything written to standard output
+    pub stdout: String,
+    /// Everything written to standard error
+    pub stderr: String
+}
+
+impl JvmOutput {
+    /// Check whether the JVM exited successfully.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// Check whether the JVM crashed (e.g. from an uncaught
+    /// exception, or being killed) and, if so, return the stack trace
+    /// (or other details) reported.
+    pub fn crash(&self) -> Option<String> {
+        for c in CRASHES {
+            if let Some(i) = self.stderr.find(c) {
+                return Some(self.stderr[i..].trim_end().to_string());
+            }
+        }
+        match self.status.code() {
+            Some(_) => None,
+            None => Some(format!("JVM terminated ({})",self.status))
+        }
+    }
+}
+
+impl fmt::Display for JvmOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}",self.stdout,self.stderr)
     }
 }
 
@@ -51,3 +375,355 @@ pub fn classpath_sep() -> &'static str {
 pub fn classpath_sep() -> &'static str {
     ";"
 }
+
+#[cfg(not(target_os = "windows"))]
+fn java_exe() -> &'static str {
+    "java"
+}
+
+#[cfg(target_os = "windows")]
+fn java_exe() -> &'static str {
+    "java.exe"
+}
+
+// ===================================================================
+// Options
+// ===================================================================
+
+/// Options for the JVM, as given by a `[jvm]` section (or a
+/// `[build.<platform>.jvm]` section for a specific platform).  For
+/// example:
+///
+/// ```toml
+/// [jvm]
+/// heap = "4g"
+/// args = ["-XX:+UseG1GC"]
+/// timeout = 600
+///
+/// [jvm.properties]
+/// "wy.verbose" = "true"
+/// ```
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct JvmOptions {
+    /// Maximum heap size (i.e. `-Xmx`)
+    pub heap: Option<String>,
+    /// System properties (i.e. `-D`)
+    pub properties: Vec<(String,String)>,
+    /// Any other arguments
+    pub args: Vec<String>,
+    /// Wall-clock time (in seconds) after which the JVM is killed
+    pub timeout: Option<u64>
+}
+
+impl JvmOptions {
+    /// Extract the options within a given section of a configuration
+    /// (e.g. `jvm` or `build.boogie.jvm`), which need not exist.
+    pub fn from_config(config: &Config, section: &[&str]) -> Result<JvmOptions,config::Error> {
+        let mut path = section.to_vec();
+        path.push("heap");
+        let heap = match config.get_string(&Key::new(&path)) {
+            Ok(h) => Some(h),
+            Err(config::Error::Invalid(_)) => None,
+            Err(e) => { return Err(e); }
+        };
+        path.pop();
+        path.push("args");
+        let args = match config.get_string_array(&Key::new(&path)) {
+            Ok(args) => args,
+            Err(config::Error::Invalid(_)) => Vec::new(),
+            Err(e) => { return Err(e); }
+        };
+        path.pop();
+        path.push("properties");
+        let properties = match config.get_strings(&Key::new(&path)) {
+            Ok(ps) => ps,
+            Err(config::Error::Invalid(_)) => Vec::new(),
+            Err(e) => { return Err(e); }
+        };
+        path.pop();
+        path.push("timeout");
+        let key = Key::new(&path);
+        let timeout = match config.get_int(&key) {
+            Ok(t) if t >= 0 => Some(t as u64),
+            Ok(_) => { return Err(config::Error::Expected(Type::Int,key.to_string())); }
+            Err(config::Error::Invalid(_)) => None,
+            Err(e) => { return Err(e); }
+        };
+        Ok(JvmOptions{heap,properties,args,timeout})
+    }
+
+    /// Combine these options with some which override them.  The
+    /// heap size, timeout and any properties given replace these,
+    /// whilst any arguments given follow these.
+    pub fn merge(&self, other: &JvmOptions) -> JvmOptions {
+        let heap = other.heap.clone().or(self.heap.clone());
+        let mut properties : Vec<(String,String)> = self.properties.iter().filter(|(k,_)| !other.properties.iter().any(|(j,_)| j == k)).cloned().collect();
+        properties.extend(other.properties.iter().cloned());
+        let mut args = self.args.clone();
+        args.extend(other.args.iter().cloned());
+        let timeout = other.timeout.or(self.timeout);
+        JvmOptions{heap,properties,args,timeout}
+    }
+
+    /// Apply any overrides given in the environment (i.e. by
+    /// `WHILEY_JVM_HEAP` and `WHILEY_JVM_ARGS`).  This allows
+    /// (for example) CI runners with less memory to adjust the heap.
+    pub fn with_env(mut self) -> JvmOptions {
+        if let Ok(heap) = env::var(HEAP_VAR) {
+            if !heap.is_empty() {
+                self.heap = Some(heap);
+            }
+        }
+        if let Ok(args) = env::var(ARGS_VAR) {
+            self.args.extend(args.split_whitespace().map(str::to_string));
+        }
+        self
+    }
+
+    /// Convert these options into arguments for the JVM.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(heap) = &self.heap {
+            args.push(format!("-Xmx{}",heap));
+        }
+        for (k,v) in &self.properties {
+            args.push(format!("-D{}={}",k,v));
+        }
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
+
+// ===================================================================
+// Discovery
+// ===================================================================
+
+/// Find a Java runtime of (at least) a given version.  Candidates are
+/// considered in order: that within `JAVA_HOME` (if set), those
+/// configured (e.g. by `[jvm] java`), and then those found on `PATH`.
+/// The first which runs and is recent enough is chosen.  Otherwise,
+/// the error reports why each candidate was rejected.
+pub fn find_java(configured: &[PathBuf], version: u32) -> Result<PathBuf,JavaError> {
+    let mut candidates = Vec::new();
+    if let Some(home) = env::var_os("JAVA_HOME") {
+        candidates.push(("JAVA_HOME",PathBuf::from(home).join("bin").join(java_exe())));
+    }
+    for c in configured {
+        candidates.push(("[jvm] java",c.clone()));
+    }
+    if let Some(path) = env::var_os("PATH") {
+        for dir in env::split_paths(&path) {
+            let java = dir.join(java_exe());
+            // NOTE: only existing files are candidates here
+            if java.is_file() {
+                candidates.push(("PATH",java));
+            }
+        }
+    }
+    // Check each in turn
+    let mut rejected = Vec::new();
+    for (source,java) in candidates {
+        let reason = match java_version(&java) {
+            Some(v) if v >= version => {
+                info!("Using java {} (version {}, from {})",java.display(),v,source);
+                return Ok(java);
+            }
+            Some(v) => format!("version {} is too old",v),
+            None if java.exists() => "could not determine version".to_string(),
+            None => "not found".to_string()
+        };
+        info!("Rejected java {} (from {}): {}",java.display(),source,reason);
+        rejected.push(format!("{} (from {}): {}",java.display(),source,reason));
+    }
+    Err(JavaError{version,rejected})
+}
+
+/// Determine the Java runtime given by a configured path (e.g. by
+/// `[jvm] java`).  A bare name (e.g. `java11`) is looked for on
+/// `PATH`, whilst any other path is relative to a given directory
+/// (unless it is absolute).
+pub fn configured_java(dir: &Path, java: &str) -> PathBuf {
+    let path = Path::new(java);
+    if !path.is_absolute() && path.components().count() == 1 {
+        if let Some(paths) = env::var_os("PATH") {
+            let name = format!("{}{}",java,env::consts::EXE_SUFFIX);
+            for d in env::split_paths(&paths) {
+                for f in [d.join(java),d.join(&name)] {
+                    if f.is_file() {
+                        return f;
+                    }
+                }
+            }
+        }
+    }
+    dir.join(path)
+}
+
+/// Determine the (major) version of a given Java runtime by running
+/// `java -version`.  For example, this is `8` for `1.8.0_292` and
+/// `17` for `17.0.15`.
+pub fn java_version(java: &Path) -> Option<u32> {
+    let output = Command::new(java).arg("-version").output().ok()?;
+    // NOTE: version is reported on stderr
+    let text = String::from_utf8_lossy(&output.stderr);
+    let start = text.find("version \"")? + 9;
+    let end = start + text[start..].find('"')?;
+    parse_version(&text[start..end])
+}
+
+/// Parse the (major) version from a Java version string.  Before Java
+/// 9, this had the form `1.8.0_292`.
+fn parse_version(version: &str) -> Option<u32> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    match parts.next()?.parse::<u32>().ok()? {
+        1 => parts.next()?.parse::<u32>().ok(),
+        v => Some(v)
+    }
+}
+
+// ===================================================================
+// Java Error
+// ===================================================================
+
+#[derive(Clone)]
+pub struct JavaError {
+    /// Minimum version required
+    pub version: u32,
+    /// Each candidate rejected, along with the reason why
+    pub rejected: Vec<String>
+}
+
+impl fmt::Display for JavaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no suitable java found (version {} or later required)",self.version)?;
+        for r in &self.rejected {
+            write!(f, "\n  rejected {}",r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for JavaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}",self)
+    }
+}
+
+impl Error for JavaError {}
+
+// ===================================================================
+// Timeout Error
+// ===================================================================
+
+#[derive(Clone)]
+pub struct TimeoutError {
+    pub seconds: u64
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "java timed out after {} second(s)",self.seconds)
+    }
+}
+
+impl fmt::Debug for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "java timed out after {} second(s)",self.seconds)
+    }
+}
+
+impl Error for TimeoutError {}
+
+// ===================================================================
+// Tests
+// ===================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(parse_version("1.8.0_292"),Some(8));
+        assert_eq!(parse_version("17.0.2"),Some(17));
+        assert_eq!(parse_version("21"),Some(21));
+        assert_eq!(parse_version("21-ea"),Some(21));
+    }
+
+    #[test]
+    fn parse_invalid_versions() {
+        assert_eq!(parse_version(""),None);
+        assert_eq!(parse_version("java"),None);
+        assert_eq!(parse_version("1"),None);
+        assert_eq!(parse_version("1.x"),None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn configured_java_on_path() {
+        // A bare name is found on PATH
+        let sh = configured_java(Path::new("/nowhere"),"sh");
+        assert!(sh.is_absolute() && sh.is_file());
+        assert_eq!(sh.file_name().unwrap(),"sh");
+    }
+
+    #[test]
+    fn configured_java_not_on_path() {
+        let dir = Path::new("/nowhere");
+        // A bare name not on PATH is relative to the directory
+        assert_eq!(configured_java(dir,"no-such-java"),dir.join("no-such-java"));
+        // As is any other relative path, even if on PATH
+        assert_eq!(configured_java(dir,"bin/sh"),dir.join("bin/sh"));
+        let java = env::temp_dir().join("java");
+        assert_eq!(configured_java(dir,java.to_str().unwrap()),java);
+    }
+
+    #[test]
+    fn options_from_env() {
+        // NOTE: no other test uses these variables
+        let options = JvmOptions{heap:Some("1g".to_string()),args:vec!["-ea".to_string()],..Default::default()};
+        env::set_var(HEAP_VAR,"4g");
+        env::set_var(ARGS_VAR," -Xss8m  -XX:+UseSerialGC ");
+        let o = options.clone().with_env();
+        assert_eq!(o.heap,Some("4g".to_string()));
+        assert_eq!(o.args,vec!["-ea","-Xss8m","-XX:+UseSerialGC"]);
+        // An empty heap does not override that configured
+        env::set_var(HEAP_VAR,"");
+        env::remove_var(ARGS_VAR);
+        assert_eq!(options.clone().with_env(),options);
+        env::remove_var(HEAP_VAR);
+        assert_eq!(options.clone().with_env(),options);
+    }
+
+    #[cfg(unix)]
+    fn output(status: i32, stderr: &str) -> JvmOutput {
+        use std::os::unix::process::ExitStatusExt;
+        JvmOutput{status:ExitStatus::from_raw(status),stdout:String::new(),stderr:stderr.to_string()}
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crash_from_exception() {
+        let out = output(1 << 8,"main.whiley:1: error\nException in thread \"main\" java.lang.NullPointerException\n\tat wyc.Main\n");
+        assert!(!out.success());
+        assert_eq!(out.crash().unwrap(),"Exception in thread \"main\" java.lang.NullPointerException\n\tat wyc.Main");
+        let out = output(1 << 8,"Error: Could not find or load main class wyc.Main\n");
+        assert_eq!(out.crash().unwrap(),"Error: Could not find or load main class wyc.Main");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crash_from_signal() {
+        // Killed (e.g. by the OOM killer)
+        let out = output(9,"");
+        assert!(out.crash().unwrap().starts_with("JVM terminated"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_crash() {
+        assert_eq!(output(0,"").crash(),None);
+        // Failures reported by the program itself are not crashes
+        assert_eq!(output(1 << 8,"main.whiley:1: unknown variable\n").crash(),None);
+    }
+}
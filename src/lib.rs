@@ -1,11 +1,17 @@
 pub mod build;
+pub mod cache;
 pub mod command;
 pub mod config;
+pub mod fetch;
+pub mod index;
 pub mod jvm;
+pub mod lock;
 pub mod maven;
 pub mod package;
 pub mod platform;
+pub mod toolchain;
 mod util;
+pub mod workspace;
 
 use std::error::Error;
 use std::path::{Path,PathBuf};
@@ -18,12 +24,18 @@ use log4rs::append::console::ConsoleAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::{PatternEncoder};
 use reqwest::Url;
-use crate::maven::{MavenArtifact,MavenResolver};
+use crate::build::{JVM_JAVA,MAVEN_OVERRIDES,MAVEN_REPOSITORIES};
+use crate::maven::{MavenArtifact,MavenResolver,Override,OverrideError};
 use crate::platform::{whiley,quickcheck,javascript,boogie};
 
 /// Default URL from which to locate Maven dependencies.
 const MAVEN_CENTRAL : &str = "https://repo1.maven.org/maven2/";
 
+/// Name of the user-level configuration file within WHILEYHOME.
+/// This currently supports `[maven] repositories = [...]`,
+/// `[maven.overrides]` and `[jvm] java = ...`.
+pub const USER_CONFIG : &str = "config.toml";
+
 pub fn init_logging(level: LevelFilter) {
     let encoder = PatternEncoder::new("[{l}] {m}{n}");
     //
@@ -60,12 +72,26 @@ pub fn init_whileyhome() -> PathBuf {
     // Create Whiley home directory (if doesn't exist)
     if !whileyhome.as_path().exists() {
 	info!("Creating directory {} ...",whileyhome.display());
-	fs::create_dir(whileyhome.as_path()).unwrap();
+	// NOTE: this tolerates concurrent invocations creating it
+	fs::create_dir_all(whileyhome.as_path()).unwrap();
     }
     // Done
     whileyhome
 }
 
+/// Determine the read-only layers beneath WHILEYHOME, as given by the
+/// WHILEYLAYERS environment variable (e.g.
+/// `WHILEYLAYERS=/opt/whiley`).  Each layer has the same structure as
+/// WHILEYHOME, and is searched (in order) for packages and jars not
+/// found in WHILEYHOME.  Layers are never written, which allows them
+/// to be provisioned once and shared (e.g. on lab machines).
+pub fn init_layers() -> Vec<PathBuf> {
+    match env::var_os("WHILEYLAYERS") {
+	Some(val) => env::split_paths(&val).filter(|p| !p.as_os_str().is_empty()).collect(),
+	None => Vec::new()
+    }
+}
+
 /// Construct a default path for WHILEYHOME which exists relative to
 /// the user's home directory.
 fn default_whileyhome() -> PathBuf {
@@ -75,25 +101,180 @@ fn default_whileyhome() -> PathBuf {
 }
 
 /// Initialise classpath for a given set of Maven dependencies.  This
-/// means resolving those dependencies as necessary from Maven
-/// central.
-pub fn init_classpath(whileyhome: &Path, deps : &[&str]) -> Result<Vec<PathBuf>,Box<dyn Error>> {
+/// means resolving those dependencies (including transitive ones) as
+/// necessary from the configured Maven repositories.
+pub fn init_classpath(whileyhome: &Path, repositories: &[Url], layers: &[PathBuf], deps : &[String]) -> Result<Vec<PathBuf>,Box<dyn Error>> {
+    let artifacts = init_artifacts(whileyhome,repositories,layers,deps)?;
+    // Done
+    Ok(artifacts.into_iter().map(|(_,jar)| jar).collect())
+}
+
+/// Resolve a given set of Maven dependencies, including any
+/// dependencies they have (as determined by their POM files).  This
+/// returns the coordinate and jar file of every artifact required in
+/// classpath order.  Any layers given (e.g. a project's vendor
+/// directory) are searched before those beneath WHILEYHOME.  Any
+/// overrides configured for the user are applied, such that a
+/// developer can use (for example) a locally built compiler.
+pub fn init_artifacts(whileyhome: &Path, repositories: &[Url], layers: &[PathBuf], deps : &[String]) -> Result<Vec<(String,PathBuf)>,Box<dyn Error>> {
+    let resolver = init_maven(whileyhome,repositories,layers)?;
+    let artifacts = resolve_roots(&resolver,deps)?;
+    // Download any missing jars (which are not overridden)
+    let remote : Vec<_> = artifacts.iter().filter(|a| resolver.overridden(a).is_none()).cloned().collect();
+    let mut jars = resolver.get_all(&remote)?;
+    // Reassemble in classpath order
+    jars.reverse();
+    let mut classpath = Vec::new();
+    for a in &artifacts {
+	let jar = match resolver.overridden(a) {
+	    Some(o) => {
+		info!("Overriding {} with {}",a,o.path.display());
+		o.path.clone()
+	    }
+	    None => jars.pop().unwrap()
+	};
+	classpath.push((a.to_string(),jar));
+    }
+    // Done
+    Ok(classpath)
+}
+
+/// Determine the overrides configured for the user (i.e. in
+/// `config.toml` within WHILEYHOME).  These map Maven coordinates to
+/// local jar files or class directories, either for a specific
+/// version or for all versions.  For example:
+///
+/// ```toml
+/// [maven.overrides]
+/// "org.whiley:wyc" = "/home/dave/WhileyCompiler/target/classes"
+/// "org.whiley:wyjs:0.10.5" = "/home/dave/WhileyJavaScript/wyjs.jar"
+/// ```
+///
+/// Relative paths are relative to WHILEYHOME.
+pub fn init_overrides(whileyhome: &Path) -> Result<Vec<Override>,Box<dyn Error>> {
+    let mut overrides = Vec::new();
+    if let Some(config) = read_user_config(whileyhome)? {
+	for (coordinate,path) in config.get_strings(&MAVEN_OVERRIDES).unwrap_or_default() {
+	    let path = whileyhome.join(path);
+	    let n = coordinate.split(':').count();
+	    if n != 2 && n != 3 {
+		let message = "expected group:artifact or group:artifact:version".to_string();
+		return Err(Box::new(OverrideError{coordinate,message}));
+	    } else if !path.exists() {
+		let message = format!("{} does not exist",path.display());
+		return Err(Box::new(OverrideError{coordinate,message}));
+	    }
+	    overrides.push(Override{coordinate,path});
+	}
+    }
+    Ok(overrides)
+}
+
+/// Determine the POM files describing a given set of Maven
+/// dependencies, including any dependencies they have and any parent
+/// POMs.  These are needed to resolve the dependencies again later.
+/// Those of overridden artifacts are omitted.  Each is paired with
+/// its path within the repository (see `MavenResolver::pom_files()`).
+pub fn init_poms(whileyhome: &Path, repositories: &[Url], layers: &[PathBuf], deps : &[String]) -> Result<Vec<(String,PathBuf)>,Box<dyn Error>> {
+    let resolver = init_maven(whileyhome,repositories,layers)?;
+    let mut poms = Vec::new();
+    for a in resolve_roots(&resolver,deps)? {
+	if resolver.overridden(&a).is_some() {
+	    continue;
+	}
+	for p in resolver.pom_files(&a)? {
+	    if !poms.contains(&p) {
+		poms.push(p);
+	    }
+	}
+    }
+    Ok(poms)
+}
+
+/// Construct a resolver for the Maven cache within WHILEYHOME, which
+/// applies any overrides configured for the user.
+fn init_maven(whileyhome: &Path, repositories: &[Url], layers: &[PathBuf]) -> Result<MavenResolver<PathBuf>,Box<dyn Error>> {
     // Append maven into Whiley home
     let mut mavenhome = PathBuf::from(whileyhome);
     mavenhome.push("maven");
-    // Parse the base URL
-    let base_url = Url::parse(MAVEN_CENTRAL).unwrap();
+    // Determine repositories to use
+    let urls = init_repositories(whileyhome,repositories)?;
     // Construct Maven resolver
-    let resolver = MavenResolver::new(mavenhome, base_url);
-    // Begin
-    let mut classpath = Vec::new();
-    //
+    let resolver = MavenResolver::new(mavenhome, urls)?;
+    // Search project layers, then installed toolchains
+    let mut layers = cache_layers(layers,"maven");
+    layers.extend(toolchain::layers(whileyhome)?);
+    Ok(resolver.with_layers(layers).with_overrides(init_overrides(whileyhome)?))
+}
+
+/// Resolve a given set of Maven dependencies (including transitive
+/// ones) using a given resolver.
+fn resolve_roots(resolver: &MavenResolver<PathBuf>, deps: &[String]) -> Result<Vec<MavenArtifact>,Box<dyn Error>> {
+    let mut roots = Vec::new();
     for dep in deps {
-    	let mdep = MavenArtifact::new(dep).unwrap();
-	classpath.push(resolver.get(mdep)?);
+    	roots.push(MavenArtifact::new(dep).unwrap());
     }
-    // Done
-    Ok(classpath)
+    resolver.resolve(&roots)
+}
+
+/// Determine the read-only caches of a given kind (e.g. `maven` or
+/// `repository`) to search beneath WHILEYHOME.  Those within any
+/// layers given come first, followed by those within the layers
+/// beneath WHILEYHOME.
+pub fn cache_layers(layers: &[PathBuf], name: &str) -> Vec<PathBuf> {
+    layers.iter().cloned().chain(init_layers()).map(|l| l.join(name)).collect()
+}
+
+/// Determine the Maven repositories to use, in the order they should
+/// be tried.  Those configured for a project are followed by those
+/// configured for the user (i.e. in `config.toml` within WHILEYHOME).
+/// When none are configured, Maven central is used.
+pub fn init_repositories(whileyhome: &Path, repositories: &[Url]) -> Result<Vec<Url>,Box<dyn Error>> {
+    let mut urls = repositories.to_vec();
+    // Read user configuration (if it exists)
+    if let Some(config) = read_user_config(whileyhome)? {
+        for r in config.get_string_array(&MAVEN_REPOSITORIES).unwrap_or_default() {
+            match index::parse_root_from(whileyhome,&r) {
+                Some(url) => urls.push(url),
+                None => {
+                    return Err(Box::new(config::Error::Invalid(MAVEN_REPOSITORIES.to_string())));
+                }
+            }
+        }
+    }
+    if urls.is_empty() {
+        urls.push(Url::parse(MAVEN_CENTRAL)?);
+    }
+    Ok(urls)
+}
+
+/// Find a Java runtime of (at least) a given version.  Any runtime
+/// configured for a project (i.e. by `[jvm] java`) is considered
+/// before any configured for the user (i.e. in `config.toml` within
+/// WHILEYHOME).  Relative paths in the latter are relative to
+/// WHILEYHOME, whilst bare names (e.g. `java11`) are looked for on
+/// `PATH`.
+pub fn init_java(whileyhome: &Path, java: Option<&Path>, version: u32) -> Result<PathBuf,Box<dyn Error>> {
+    let mut configured : Vec<PathBuf> = java.iter().map(|p| p.to_path_buf()).collect();
+    if let Some(config) = read_user_config(whileyhome)? {
+        match config.get_string(&JVM_JAVA) {
+            Ok(p) => configured.push(jvm::configured_java(whileyhome,&p)),
+            Err(config::Error::Invalid(_)) => {}
+            Err(e) => { return Err(Box::new(e)); }
+        }
+    }
+    Ok(jvm::find_java(&configured,version)?)
+}
+
+/// Read the user-level configuration within WHILEYHOME (if it
+/// exists).
+fn read_user_config(whileyhome: &Path) -> Result<Option<config::Config>,Box<dyn Error>> {
+    let path = whileyhome.join(USER_CONFIG);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)?;
+    Ok(Some(config::Config::from_str(&contents)?))
 }
 
 /// Initialise the default platform registry.  This basically provides
@@ -0,0 +1,470 @@
+use std::error::Error;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::str::FromStr;
+use toml;
+use toml::Value;
+use toml::value::{Array,Table};
+use crate::config;
+use crate::config::Type;
+use crate::fetch;
+use crate::fetch::Mode;
+use semver::Version;
+use crate::package::{Dependency,Package};
+
+/// Name of the lock file stored alongside the build configuration.
+pub const LOCK_FILE : &str = "wy.lock";
+
+const HEADER : &str = "# This file is automatically generated by wy.\n# It is not intended for manual editing.\n\n";
+
+// ===================================================================
+// Locked Package
+// ===================================================================
+
+/// Records the exact version of a package used in a build, along
+/// with where it came from and the checksum of its zip file.
+#[derive(Clone,Debug,PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    pub checksum: String,
+    /// Names of the packages this package depends upon
+    pub dependencies: Vec<String>
+}
+
+impl LockedPackage {
+    /// Convert this locked package back into a package.
+    pub fn to_package(&self) -> Result<Package,semver::Error> {
+        let version = Version::parse(&self.version)?;
+        Ok(Package::new(self.name.clone(),version))
+    }
+}
+
+// ===================================================================
+// Locked Artifact
+// ===================================================================
+
+/// Records a Maven artifact (i.e. jar file) used by one or more
+/// platforms in a build, along with the checksum of its jar file.
+#[derive(Clone,Debug,PartialEq)]
+pub struct LockedArtifact {
+    pub coordinate: String,
+    pub checksum: String
+}
+
+// ===================================================================
+// Lock
+// ===================================================================
+
+/// Represents the contents of a lock file.  This records exactly
+/// which packages and Maven artifacts were used in a build, thus
+/// allowing subsequent builds to be reproduced exactly.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Lock {
+    pub packages: Vec<LockedPackage>,
+    pub artifacts: Vec<LockedArtifact>
+}
+
+impl Lock {
+    /// Read the lock file at a given path, returning `None` if no
+    /// such file exists.
+    pub fn read(path: &Path) -> Result<Option<Lock>,Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(Some(contents.parse::<Lock>()?))
+    }
+
+    /// Read the lock file at a given path for use in a build, where
+    /// a missing lock file is treated as empty.  When frozen, a
+    /// missing lock file is reported since it cannot be created.
+    pub fn load(path: &Path) -> Result<Lock,Box<dyn Error>> {
+        match Lock::read(path)? {
+            Some(lock) => Ok(lock),
+            None if fetch::mode() == Mode::Frozen => Err(Box::new(FrozenError::missing(path))),
+            None => Ok(Lock::default())
+        }
+    }
+
+    /// Write this lock file to a given path.  When frozen, the lock
+    /// file cannot be changed and, instead, an error is reported if
+    /// it would be.  An empty lock file need not exist.
+    pub fn write(&self, path: &Path) -> Result<(),Box<dyn Error>> {
+        if fetch::mode() == Mode::Frozen {
+            match Lock::read(path)? {
+                None if self == &Lock::default() => {}
+                None => { return Err(Box::new(FrozenError::missing(path))); }
+                Some(l) if &l != self => {
+                    let message = format!("{} would be updated",path.display());
+                    return Err(Box::new(FrozenError{message}));
+                }
+                Some(_) => {}
+            }
+            return Ok(());
+        }
+        fs::write(path,self.to_string())?;
+        Ok(())
+    }
+
+    /// Determine the locked packages needed for a given set of
+    /// (direct) dependencies.  This returns `None` if the packages
+    /// recorded in this lock file are not consistent with them, in
+    /// which case package resolution must be performed again.
+    /// Otherwise, it returns those packages reachable from the
+    /// dependencies in the order they were first encountered.
+    pub fn resolve(&self, deps: &[Dependency]) -> Option<Vec<LockedPackage>> {
+        let mut packages : Vec<LockedPackage> = Vec::new();
+        let mut worklist : VecDeque<String> = VecDeque::new();
+        for d in deps {
+            let p = self.package(d.name())?.to_package().ok()?;
+            if !d.matches(&p) {
+                return None;
+            }
+            worklist.push_back(d.name().to_string());
+        }
+        //
+        while let Some(name) = worklist.pop_front() {
+            if packages.iter().any(|p| p.name == name) {
+                continue;
+            }
+            let p = self.package(&name)?;
+            worklist.extend(p.dependencies.iter().cloned());
+            packages.push(p.clone());
+        }
+        Some(packages)
+    }
+
+    /// Remove any packages which are not reachable from a given set
+    /// of (direct) dependencies, along with any artifacts not in a
+    /// given set of coordinates.
+    pub fn prune(&mut self, deps: &[Dependency], coordinates: &[String]) {
+        if let Some(ps) = self.resolve(deps) {
+            self.packages = ps;
+        }
+        self.artifacts.retain(|a| coordinates.contains(&a.coordinate));
+    }
+
+    /// Record a given package, replacing any existing package of the
+    /// same name.
+    pub fn insert_package(&mut self, package: LockedPackage) {
+        match self.packages.iter_mut().find(|p| p.name == package.name) {
+            Some(p) => *p = package,
+            None => self.packages.push(package)
+        }
+    }
+
+    /// Record a given artifact, replacing any existing artifact with
+    /// the same coordinate.
+    pub fn insert_artifact(&mut self, artifact: LockedArtifact) {
+        match self.artifacts.iter_mut().find(|a| a.coordinate == artifact.coordinate) {
+            Some(a) => *a = artifact,
+            None => self.artifacts.push(artifact)
+        }
+    }
+
+    /// Get the locked package with a given name (if it exists).
+    pub fn package(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| p.name == name)
+    }
+
+    /// Get the locked artifact with a given coordinate (if it exists).
+    pub fn artifact(&self, coordinate: &str) -> Option<&LockedArtifact> {
+        self.artifacts.iter().find(|a| a.coordinate == coordinate)
+    }
+}
+
+impl FromStr for Lock {
+    type Err = config::Error;
+
+    /// Parse a given string into a lock file.
+    fn from_str(contents: &str) -> Result<Lock,config::Error> {
+        let toml: Value = toml::from_str(contents)?;
+        let mut packages = Vec::new();
+        let mut artifacts = Vec::new();
+        //
+        for p in get_tables(&toml,"package")? {
+            let name = get_field(p,"package","name")?;
+            let version = get_field(p,"package","version")?;
+            let source = get_field(p,"package","source")?;
+            let checksum = get_field(p,"package","checksum")?;
+            let dependencies = get_array(p,"package","dependencies")?;
+            packages.push(LockedPackage{name,version,source,checksum,dependencies});
+        }
+        //
+        for a in get_tables(&toml,"artifact")? {
+            let coordinate = get_field(a,"artifact","coordinate")?;
+            let checksum = get_field(a,"artifact","checksum")?;
+            artifacts.push(LockedArtifact{coordinate,checksum});
+        }
+        // Done
+        Ok(Lock{packages,artifacts})
+    }
+}
+
+impl fmt::Display for Lock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut packages = Array::new();
+        for p in &self.packages {
+            let mut t = Table::new();
+            t.insert("name".to_string(),Value::String(p.name.clone()));
+            t.insert("version".to_string(),Value::String(p.version.clone()));
+            t.insert("source".to_string(),Value::String(p.source.clone()));
+            t.insert("checksum".to_string(),Value::String(p.checksum.clone()));
+            let deps = p.dependencies.iter().map(|d| Value::String(d.clone())).collect();
+            t.insert("dependencies".to_string(),Value::Array(deps));
+            packages.push(Value::Table(t));
+        }
+        let mut artifacts = Array::new();
+        for a in &self.artifacts {
+            let mut t = Table::new();
+            t.insert("coordinate".to_string(),Value::String(a.coordinate.clone()));
+            t.insert("checksum".to_string(),Value::String(a.checksum.clone()));
+            artifacts.push(Value::Table(t));
+        }
+        // NOTE: empty arrays are omitted since they cannot follow
+        // tables in the generated TOML.
+        let mut root = Table::new();
+        if !packages.is_empty() {
+            root.insert("package".to_string(),Value::Array(packages));
+        }
+        if !artifacts.is_empty() {
+            root.insert("artifact".to_string(),Value::Array(artifacts));
+        }
+        //
+        let body = toml::to_string(&Value::Table(root)).map_err(|_| fmt::Error)?;
+        write!(f,"{}{}",HEADER,body)
+    }
+}
+
+/// Extract an array of tables with a given name from the root of a
+/// lock file.  A missing array is treated as empty.
+fn get_tables<'a>(toml: &'a Value, name: &str) -> Result<Vec<&'a Table>,config::Error> {
+    let mut tables = Vec::new();
+    if let Some(v) = toml.get(name) {
+        let arr = v.as_array().ok_or_else(|| config::Error::Invalid(name.to_string()))?;
+        for e in arr {
+            tables.push(e.as_table().ok_or_else(|| config::Error::Invalid(name.to_string()))?);
+        }
+    }
+    Ok(tables)
+}
+
+/// Extract a string field from a table in a lock file.
+fn get_field(table: &Table, name: &str, field: &str) -> Result<String,config::Error> {
+    match table.get(field).and_then(|v| v.as_str()) {
+        Some(s) => Ok(s.to_string()),
+        None => Err(config::Error::Expected(Type::String,format!("{}.{}",name,field)))
+    }
+}
+
+/// Extract an (optional) string array field from a table in a lock
+/// file.  A missing field is treated as empty.
+fn get_array(table: &Table, name: &str, field: &str) -> Result<Vec<String>,config::Error> {
+    let mut res = Vec::new();
+    if let Some(v) = table.get(field) {
+        let err = || config::Error::Expected(Type::StringArray,format!("{}.{}",name,field));
+        for e in v.as_array().ok_or_else(err)? {
+            res.push(e.as_str().ok_or_else(err)?.to_string());
+        }
+    }
+    Ok(res)
+}
+
+// ===================================================================
+// Checksum Error
+// ===================================================================
+
+/// Indicates a cached file no longer matches the checksum recorded
+/// for it in the lock file.
+#[derive(Clone)]
+pub struct ChecksumError {
+    pub name: String,
+    pub path: PathBuf
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "checksum mismatch for {} ({}) recorded in {}",self.name,self.path.display(),LOCK_FILE)
+    }
+}
+
+impl fmt::Debug for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "checksum mismatch for {} ({}) recorded in {}",self.name,self.path.display(),LOCK_FILE)
+    }
+}
+
+impl Error for ChecksumError {}
+
+// ===================================================================
+// Frozen Error
+// ===================================================================
+
+/// Indicates the lock file does not reflect what a build requires
+/// (or does not exist), but it cannot be updated because `--frozen`
+/// was given.
+#[derive(Clone)]
+pub struct FrozenError {
+    pub message: String
+}
+
+impl FrozenError {
+    /// Report that the lock file at a given path does not exist.
+    pub fn missing(path: &Path) -> Self {
+        FrozenError{message:format!("{} is missing",path.display())}
+    }
+}
+
+impl fmt::Display for FrozenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} but --frozen was given",self.message)
+    }
+}
+
+impl fmt::Debug for FrozenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} but --frozen was given",self.message)
+    }
+}
+
+impl Error for FrozenError {}
+
+// ===================================================================
+// Tests
+// ===================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK : &str = r#"
+[[package]]
+name = "app"
+version = "1.0.0"
+source = "https://example.org/"
+checksum = "aa"
+dependencies = ["std"]
+
+[[package]]
+name = "std"
+version = "0.3.2"
+source = "https://example.org/"
+checksum = "bb"
+
+[[package]]
+name = "old"
+version = "0.1.0"
+source = "https://example.org/"
+checksum = "cc"
+dependencies = []
+
+[[artifact]]
+coordinate = "org.whiley:wyc:0.10.18"
+checksum = "dd"
+"#;
+
+    fn dep(name: &str, req: &str) -> Dependency {
+        Dependency::parse(name.to_string(),req).unwrap()
+    }
+
+    fn names(packages: &[LockedPackage]) -> Vec<&str> {
+        packages.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_lock() {
+        let lock : Lock = LOCK.parse().unwrap();
+        assert_eq!(names(&lock.packages),vec!["app","std","old"]);
+        let app = lock.package("app").unwrap();
+        assert_eq!(app.version,"1.0.0");
+        assert_eq!(app.checksum,"aa");
+        assert_eq!(app.dependencies,vec!["std".to_string()]);
+        // Missing dependencies are empty
+        assert!(lock.package("std").unwrap().dependencies.is_empty());
+        assert_eq!(lock.artifact("org.whiley:wyc:0.10.18").unwrap().checksum,"dd");
+    }
+
+    #[test]
+    fn parse_empty_lock() {
+        assert_eq!("".parse::<Lock>().unwrap(),Lock::default());
+    }
+
+    #[test]
+    fn parse_invalid_lock() {
+        assert!("[[package]]\nname = \"app\"\n".parse::<Lock>().is_err());
+        assert!("[[artifact]]\ncoordinate = 1\nchecksum = \"dd\"\n".parse::<Lock>().is_err());
+        assert!("package = 1\n".parse::<Lock>().is_err());
+    }
+
+    #[test]
+    fn round_trip_lock() {
+        let lock : Lock = LOCK.parse().unwrap();
+        let text = lock.to_string();
+        assert!(text.starts_with(HEADER));
+        assert_eq!(text.parse::<Lock>().unwrap(),lock);
+        // Empty lock files also survive
+        let empty = Lock::default().to_string();
+        assert_eq!(empty.parse::<Lock>().unwrap(),Lock::default());
+    }
+
+    #[test]
+    fn resolve_reachable() {
+        let lock : Lock = LOCK.parse().unwrap();
+        let ps = lock.resolve(&[dep("app","^1.0")]).unwrap();
+        assert_eq!(names(&ps),vec!["app","std"]);
+        let ps = lock.resolve(&[dep("old","0.1"),dep("std","^0.3")]).unwrap();
+        assert_eq!(names(&ps),vec!["old","std"]);
+        assert_eq!(lock.resolve(&[]).unwrap(),vec![]);
+    }
+
+    #[test]
+    fn resolve_inconsistent() {
+        let lock : Lock = LOCK.parse().unwrap();
+        // Locked version no longer matches
+        assert_eq!(lock.resolve(&[dep("app","^2.0")]),None);
+        // Package was never locked
+        assert_eq!(lock.resolve(&[dep("other","*")]),None);
+        // Locked dependency is missing
+        let mut broken = lock.clone();
+        broken.packages.retain(|p| p.name != "std");
+        assert_eq!(broken.resolve(&[dep("app","^1.0")]),None);
+    }
+
+    #[test]
+    fn prune_unreachable() {
+        let mut lock : Lock = LOCK.parse().unwrap();
+        lock.prune(&[dep("app","^1.0")],&[]);
+        assert_eq!(names(&lock.packages),vec!["app","std"]);
+        assert!(lock.artifacts.is_empty());
+        //
+        let mut lock : Lock = LOCK.parse().unwrap();
+        lock.prune(&[dep("old","*")],&["org.whiley:wyc:0.10.18".to_string()]);
+        assert_eq!(names(&lock.packages),vec!["old"]);
+        assert_eq!(lock.artifacts.len(),1);
+    }
+
+    #[test]
+    fn prune_inconsistent() {
+        // Packages are left alone when the lock cannot be used
+        let mut lock : Lock = LOCK.parse().unwrap();
+        lock.prune(&[dep("app","^2.0")],&[]);
+        assert_eq!(lock.packages.len(),3);
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut lock : Lock = LOCK.parse().unwrap();
+        let mut std = lock.package("std").unwrap().clone();
+        std.version = "0.3.3".to_string();
+        lock.insert_package(std);
+        assert_eq!(lock.packages.len(),3);
+        assert_eq!(lock.package("std").unwrap().version,"0.3.3");
+        lock.insert_artifact(LockedArtifact{coordinate:"org.whiley:wyc:0.10.18".to_string(),checksum:"ee".to_string()});
+        assert_eq!(lock.artifacts.len(),1);
+        assert_eq!(lock.artifact("org.whiley:wyc:0.10.18").unwrap().checksum,"ee");
+    }
+}
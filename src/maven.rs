@@ -1,121 +1,959 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::path::{Path,PathBuf};
-use log::{info,error};
-use reqwest;
+use log::{info,warn};
 use reqwest::Url;
+use roxmltree::{Document,Node};
+use crate::fetch;
+use crate::fetch::{CacheError,Fetcher,ResolutionError};
+use crate::util;
+use crate::util::FileLock;
+
+/// Maximum depth of parent POMs followed, which protects against
+/// cycles.
+const MAX_PARENTS : usize = 16;
+
+/// Computes the digest of some bytes as a lowercase hex string.
+type Digest = fn(&[u8]) -> String;
+
+/// Checks a file downloaded from a given URL before it is committed
+/// to the cache.
+type Check<'a> = &'a dyn Fn(&Url,&Path) -> Result<(),Box<dyn Error + Send + Sync>>;
+
+/// Checksum files published alongside artifacts in a Maven repository
+/// (in order of preference), along with the digest each uses.
+const CHECKSUMS : &[(&str,Digest)] = &[
+    ("sha256",util::sha256_bytes),
+    ("sha1",util::sha1_bytes)
+];
 
 #[derive(Clone,Debug,PartialEq)]
-pub struct MavenArtifact<'a> {
-    group_id : &'a str,
-    artifact_id : &'a str,
-    version: &'a str
+pub struct MavenArtifact {
+    group_id : String,
+    artifact_id : String,
+    version: String
 }
 
-impl<'a> MavenArtifact<'a> {
+impl MavenArtifact {
     pub fn new(desc : &str) -> Result<MavenArtifact,()> {
 	let parts = desc.split(":").collect::<Vec<&str>>();
 	//
 	if parts.len() != 3 {
 	    Err(())
 	} else {
-	    Ok(MavenArtifact{group_id:parts[0],artifact_id:parts[1],version:parts[2]})
+	    Ok(MavenArtifact::from_parts(parts[0],parts[1],parts[2]))
 	}
     }
 
+    pub fn from_parts(group_id: &str, artifact_id: &str, version: &str) -> MavenArtifact {
+        let group_id = group_id.to_string();
+        let artifact_id = artifact_id.to_string();
+        let version = version.to_string();
+        MavenArtifact{group_id,artifact_id,version}
+    }
+
+    /// Identifies this artifact regardless of its version
+    /// (e.g. `org.whiley:wyc`).
+    pub fn key(&self) -> String {
+        format!("{}:{}",self.group_id,self.artifact_id)
+    }
+
     pub fn to_jarname(&self) -> String {
-	let mut n = String::new();
-	n.push_str(self.artifact_id);
-	n.push_str("-");
-	n.push_str(self.version);
-	n.push_str(".jar");
-	n
+	self.to_filename("jar")
+    }
+
+    pub fn to_pomname(&self) -> String {
+	self.to_filename("pom")
+    }
+
+    /// Determine the path of this artifact's jar within a repository
+    /// (or cache).
+    pub fn to_jarpath(&self) -> String {
+	self.to_path(&self.to_jarname())
+    }
+
+    /// Determine the path of this artifact's POM within a repository
+    /// (or cache).
+    pub fn to_pompath(&self) -> String {
+	self.to_path(&self.to_pomname())
     }
 
     pub fn to_url(&self, base: &Url) -> Url {
+	// ERROR HANDLING
+	base.join(&self.to_jarpath()).unwrap()
+    }
+
+    /// Determine the path of a given file for this artifact within a
+    /// repository (e.g. `org/whiley/wyc/0.10.18/wyc-0.10.18.pom`).
+    pub fn to_path(&self, name: &str) -> String {
 	let mut s = String::new();
 	s.push_str(self.group_id.replace(".","/").as_str());
-	s.push_str("/");
-	s.push_str(self.artifact_id);
-	s.push_str("/");
-	s.push_str(self.version);
-	s.push_str("/");
-	s.push_str(self.to_jarname().as_str());
-	// ERROR HANDLING
-	base.join(&s).unwrap()
+	s.push('/');
+	s.push_str(&self.artifact_id);
+	s.push('/');
+	s.push_str(&self.version);
+	s.push('/');
+	s.push_str(name);
+	s
+    }
+
+    fn to_filename(&self, ext: &str) -> String {
+	format!("{}-{}.{}",self.artifact_id,self.version,ext)
     }
 }
 
-impl<'a> fmt::Display for MavenArtifact<'a> {
+impl fmt::Display for MavenArtifact {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,"{}:{}:{}",self.group_id,self.artifact_id,self.version)
     }
 }
 
+// ==========================================================
+// Override
+// ==========================================================
+
+/// Replaces a Maven artifact with a local jar file or class
+/// directory (e.g. one freshly built by a developer).
+pub struct Override {
+    /// Coordinate of the artifact being replaced.  This may omit the
+    /// version (e.g. `org.whiley:wyc`), in which case every version
+    /// is replaced.
+    pub coordinate: String,
+    pub path: PathBuf
+}
+
+impl Override {
+    /// Check whether this override applies to a given artifact.
+    pub fn matches(&self, artifact: &MavenArtifact) -> bool {
+	self.coordinate == artifact.key() || self.coordinate == artifact.to_string()
+    }
+}
+
+/// Check whether a given coordinate (e.g. `org.whiley:wyc:0.10.18`) is
+/// overridden by any of a given set of overrides.
+pub fn is_overridden(overrides: &[Override], coordinate: &str) -> bool {
+    match MavenArtifact::new(coordinate) {
+	Ok(a) => overrides.iter().any(|o| o.matches(&a)),
+	Err(_) => false
+    }
+}
+
+#[derive(Clone)]
+pub struct OverrideError {
+    pub coordinate: String,
+    pub message: String
+}
+
+impl fmt::Display for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid override for {}: {}",self.coordinate,self.message)
+    }
+}
+
+impl fmt::Debug for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid override for {}: {}",self.coordinate,self.message)
+    }
+}
+
+impl Error for OverrideError {}
+
 // ==========================================================
 // Resolver
 // ==========================================================
 
-#[derive(Clone,Debug,PartialEq)]
 pub struct MavenResolver<T: AsRef<Path>> {
-    /// Path to cache root on local filesyste
+    /// Path to cache root on local filesystem.  This has the same
+    /// layout as a repository (e.g. `org/whiley/wyc/0.10.18/...`),
+    /// such that artifacts in different groups cannot collide.
     dir: T,
-    /// Base URL for downloading Maven Jars (e.g. Maven Central)
-    url: Url
+    /// Base URLs of the repositories from which to download Maven
+    /// Jars (e.g. Maven Central).  These are tried in order.
+    urls: Vec<Url>,
+    /// Responsible for downloading jars and POMs
+    fetcher: Fetcher,
+    /// Read-only caches searched after this cache (e.g. one
+    /// provisioned by an administrator).  These are never written.
+    layers: Vec<PathBuf>,
+    /// Artifacts replaced by local files, which are therefore never
+    /// downloaded
+    overrides: Vec<Override>,
+    /// Lock on the cache, held whilst this resolver exists
+    _lock: FileLock
 }
 
 impl<T: AsRef<Path>> MavenResolver<T> {
-    pub fn new(dir: T, url: Url) -> MavenResolver<T> {
-	// Ensure cache directory exists
-	fs::create_dir_all(dir.as_ref()).unwrap();
+    pub fn new(dir: T, urls: Vec<Url>) -> Result<MavenResolver<T>,Box<dyn Error>> {
+	MavenResolver::with_fetcher(dir,urls,Fetcher::default())
+    }
+
+    /// Construct a Maven resolver which downloads files using a given
+    /// fetcher.  This blocks until no other process is using the
+    /// cache.
+    pub fn with_fetcher(dir: T, urls: Vec<Url>, fetcher: Fetcher) -> Result<MavenResolver<T>,Box<dyn Error>> {
+	// Ensure cache directory exists, and lock it
+	let _lock = FileLock::acquire(dir.as_ref())?;
 	// Done
-	MavenResolver{dir,url}
+	Ok(MavenResolver{dir,urls,fetcher,layers:Vec::new(),overrides:Vec::new(),_lock})
+    }
+
+    /// Search a given set of read-only caches for files not in this
+    /// cache.  These are searched in order.
+    pub fn with_layers(mut self, layers: Vec<PathBuf>) -> Self {
+	self.layers = layers;
+	self
+    }
+
+    /// Replace artifacts matching a given set of overrides with the
+    /// local files they give.  The jars and POMs of such artifacts
+    /// are never downloaded, though any POM already cached is used to
+    /// determine their dependencies.
+    pub fn with_overrides(mut self, overrides: Vec<Override>) -> Self {
+	self.overrides = overrides;
+	self
     }
 
-    pub fn get<'b>(&self, artifact: MavenArtifact<'b>) -> Result<PathBuf,Box<dyn Error>> {
-	// Determine jar name
-	let mut jar = PathBuf::new();
-	jar.push(self.dir.as_ref());
-	jar.push(artifact.to_jarname());
+    /// Get the override (if any) applying to a given artifact.
+    pub fn overridden(&self, artifact: &MavenArtifact) -> Option<&Override> {
+	self.overrides.iter().find(|o| o.matches(artifact))
+    }
+
+    /// Get the jar file for a given artifact, downloading it if it is
+    /// not already cached.  Downloaded jars are verified against the
+    /// checksum published alongside them before being cached, and
+    /// cached jars are verified against the checksum recorded with
+    /// them (if any).
+    pub fn get(&self, artifact: &MavenArtifact) -> Result<PathBuf,Box<dyn Error + Send + Sync>> {
+	// Determine jar location
+	let jar = self.dir.as_ref().join(artifact.to_jarpath());
 	//
-	if !jar.as_path().exists() {
-            // cache miss, try to download
-	    let url = artifact.to_url(&self.url);
-	    let response = reqwest::blocking::get(url.clone())?;
-            // Check status code
-            if response.status().is_success() {
-                info!("Downloaded {}",url.as_str());
-	        fs::write(jar.as_path(),response.bytes()?)?;
-            } else {
-                error!("Downloading {} ({:?})",url.as_str(),response.status());
-                return Err(Box::new(ResolutionError{name:artifact.to_jarname()}));
-            }
+	if let Some(cached) = self.find(&artifact.to_jarpath()) {
+	    if verify_cached(&cached)? {
+		return Ok(cached);
+	    } else if cached == jar {
+		warn!("Cached {} is corrupt, downloading again",jar.display());
+		fs::remove_file(&jar)?;
+	    } else {
+		// Cannot remove from a read-only layer
+		warn!("Cached {} is corrupt, ignoring it",cached.display());
+	    }
+	}
+	// cache miss, try to download (verifying against the published
+	// checksum, if any).  Should one checksum not be fetched
+	// (e.g. it is forbidden by a mirror), the next is tried.
+	let check = |url: &Url, part: &Path| -> Result<(),Box<dyn Error + Send + Sync>> {
+	    let bytes = fs::read(part)?;
+	    for (ext,digest) in CHECKSUMS {
+		let curl = Url::parse(&format!("{}.{}",url,ext))?;
+		let c = match self.fetcher.get(&curl) {
+		    Ok(c) => c,
+		    Err(e) => {
+			warn!("Fetching {} ({})",curl.as_str(),e);
+			None
+		    }
+		};
+		if let Some(c) = c {
+		    let expected = parse_checksum(&c);
+		    let actual = digest(&bytes);
+		    if expected != actual {
+			let name = format!("{} ({} mismatch: expected {} but got {})",artifact.to_jarname(),ext,expected,actual);
+			return Err(Box::new(ResolutionError{name}));
+		    }
+		    // Record checksum for checking cached jar later
+		    fs::write(checksum_path(&jar,ext),expected)?;
+		    return Ok(());
+		}
+	    }
+	    warn!("No checksum published for {}",url.as_str());
+	    Ok(())
+	};
+	if !self.download(&artifact.to_jarpath(),&jar,&check)? {
+	    return Err(Box::new(ResolutionError{name:artifact.to_jarname()}));
 	}
 	//
 	Ok(jar)
     }
+
+    /// Get the jar files for a given set of artifacts, downloading
+    /// those not already cached concurrently.
+    pub fn get_all(&self, artifacts: &[MavenArtifact]) -> Result<Vec<PathBuf>,Box<dyn Error>>
+    where T: Sync {
+	let missing : Vec<MavenArtifact> = artifacts.iter().filter(|a| self.find(&a.to_jarpath()).is_none()).cloned().collect();
+	fetch::fetch_all(&missing,|a| self.get(a)).map_err(|e| e as Box<dyn Error>)?;
+	artifacts.iter().map(|a| self.get(a).map_err(|e| e as Box<dyn Error>)).collect()
+    }
+
+    /// Determine the full set of artifacts needed for a given set of
+    /// artifacts.  This follows the compile and runtime dependencies
+    /// given in each artifact's POM.  When several versions of the
+    /// same artifact are reachable, the nearest one is selected
+    /// (with ties broken by declaration order) as for Maven.  The
+    /// result is in classpath order.  The POMs of all artifacts at
+    /// the same depth are fetched concurrently.  The given artifacts
+    /// must have POMs, though those they depend on need not (as for
+    /// Maven), in which case they are assumed to have no
+    /// dependencies.
+    pub fn resolve(&self, artifacts: &[MavenArtifact]) -> Result<Vec<MavenArtifact>,Box<dyn Error>>
+    where T: Sync {
+	let mut selected : Vec<MavenArtifact> = Vec::new();
+	let mut level : Vec<(MavenArtifact,Vec<String>)> = artifacts.iter().map(|a| (a.clone(),Vec::new())).collect();
+	let mut roots = true;
+	// Breadth-first traversal ensures nearest wins
+	while !level.is_empty() {
+	    let mut current : Vec<(MavenArtifact,Vec<String>)> = Vec::new();
+	    for (a,es) in level {
+		if !selected.iter().chain(current.iter().map(|(c,_)| c)).any(|s| s.key() == a.key()) {
+		    current.push((a,es));
+		}
+	    }
+	    // Fetch their POMs (though not their parents, which are
+	    // often shared)
+	    let poms : Vec<MavenArtifact> = current.iter().map(|(a,_)| a.clone()).collect();
+	    let files = fetch::fetch_all(&poms,|a| {
+		if self.overridden(a).is_some() {
+		    Ok(self.find(&a.to_pompath()))
+		} else {
+		    self.fetch(&a.to_pompath())
+		}
+	    }).map_err(|e| e as Box<dyn Error>)?;
+	    //
+	    let mut next = Vec::new();
+	    for ((artifact,exclusions),file) in current.into_iter().zip(files) {
+		let pom = match file {
+		    Some(_) => self.pom(&artifact)?,
+		    None => None
+		};
+		let pom = match pom {
+		    Some(pom) => pom,
+		    None if self.overridden(&artifact).is_some() => {
+			info!("No POM cached for {}, assuming no dependencies",artifact);
+			Pom::default()
+		    }
+		    None if roots => {
+			let name = format!("{} (no POM found)",artifact);
+			return Err(Box::new(ResolutionError{name}));
+		    }
+		    None => {
+			warn!("No POM found for {}, assuming no dependencies",artifact);
+			Pom::default()
+		    }
+		};
+		for d in &pom.dependencies {
+		    if !d.is_transitive() || exclusions.iter().any(|e| d.is_excluded_by(e)) {
+			continue;
+		    }
+		    let version = match pom.version_of(d) {
+			Some(v) => v,
+			None => {
+			    let name = format!("{} (no version for {}:{})",artifact,d.group_id,d.artifact_id);
+			    return Err(Box::new(ResolutionError{name}));
+			}
+		    };
+		    let dep = MavenArtifact::from_parts(&d.group_id,&d.artifact_id,&version);
+		    let mut es = exclusions.clone();
+		    es.extend(d.exclusions.iter().cloned());
+		    next.push((dep,es));
+		}
+		selected.push(artifact);
+	    }
+	    level = next;
+	    roots = false;
+	}
+	// Done
+	Ok(selected)
+    }
+
+    /// Get the (effective) POM for a given artifact, downloading it if
+    /// it is not already cached.  This returns `None` if the artifact
+    /// has no POM.
+    pub fn pom(&self, artifact: &MavenArtifact) -> Result<Option<Pom>,Box<dyn Error>> {
+	self.effective_pom(artifact,0)
+    }
+
+    /// Get the POM files describing a given artifact, namely its own
+    /// POM followed by those of its parents (if any).  These are
+    /// downloaded if not already cached, and each is paired with its
+    /// path within the repository.
+    pub fn pom_files(&self, artifact: &MavenArtifact) -> Result<Vec<(String,PathBuf)>,Box<dyn Error>> {
+	let mut files = Vec::new();
+	let mut next = Some(artifact.clone());
+	while let Some(a) = next {
+	    if files.len() > MAX_PARENTS {
+		let name = format!("{} (too many parent POMs)",artifact);
+		return Err(Box::new(ResolutionError{name}));
+	    }
+	    let path = match self.fetch(&a.to_pompath()).map_err(|e| e as Box<dyn Error>)? {
+		Some(p) => p,
+		None => { break; }
+	    };
+	    next = Pom::parse(&fs::read_to_string(&path)?).and_then(|p| p.parent);
+	    files.push((a.to_pompath(),path));
+	}
+	Ok(files)
+    }
+
+    /// Construct the effective POM for a given artifact, which
+    /// includes everything inherited from its parent(s).
+    fn effective_pom(&self, artifact: &MavenArtifact, depth: usize) -> Result<Option<Pom>,Box<dyn Error>> {
+	let path = match self.fetch(&artifact.to_pompath()).map_err(|e| e as Box<dyn Error>)? {
+	    Some(p) => p,
+	    None => { return Ok(None); }
+	};
+	let contents = fs::read_to_string(&path)?;
+	let mut pom = match Pom::parse(&contents) {
+	    Some(pom) => pom,
+	    None => {
+		let name = format!("{} (invalid POM)",artifact);
+		return Err(Box::new(ResolutionError{name}));
+	    }
+	};
+	// Inherit from parent (if applicable)
+	if let Some(parent) = pom.parent.clone() {
+	    if depth >= MAX_PARENTS {
+		let name = format!("{} (too many parent POMs)",artifact);
+		return Err(Box::new(ResolutionError{name}));
+	    }
+	    match self.effective_pom(&parent,depth+1)? {
+		Some(p) => pom.inherit(&p),
+		None => {
+		    let name = format!("{} (missing parent POM {})",artifact,parent);
+		    return Err(Box::new(ResolutionError{name}));
+		}
+	    }
+	}
+	pom.interpolate();
+	Ok(Some(pom))
+    }
+
+    /// Ensure a given file (i.e. a POM, such as
+    /// `org/whiley/wyc/0.10.18/wyc-0.10.18.pom`) is in the local
+    /// cache, downloading it if necessary.  This returns `None` if the file
+    /// does not exist remotely.  When offline, a file which is not
+    /// cached is an error, since whether it exists is unknown.
+    fn fetch(&self, path: &str) -> Result<Option<PathBuf>,Box<dyn Error + Send + Sync>> {
+	if let Some(file) = self.find(path) {
+	    return Ok(Some(file));
+	}
+	let file = self.dir.as_ref().join(path);
+	//
+	if !self.download(path,&file,&|_,_| Ok(()))? {
+            return Ok(None);
+	}
+	//
+	Ok(Some(file))
+    }
+
+    /// Locate a given file (e.g. `org/whiley/wyc/0.10.18/wyc-0.10.18.jar`)
+    /// in this cache or, failing that, in one of the read-only layers
+    /// beneath it.
+    fn find(&self, path: &str) -> Option<PathBuf> {
+	let mut dirs = vec![self.dir.as_ref()];
+	dirs.extend(self.layers.iter().map(PathBuf::as_path));
+	dirs.into_iter().map(|d| d.join(path)).find(|f| f.exists())
+    }
+
+    /// Download a given file (e.g. `org/whiley/wyc/0.10.18/wyc-0.10.18.jar`)
+    /// into the cache from the first repository which has it.  The
+    /// download is checked using a given function before being
+    /// committed.  This returns `false` if no repository has the file.
+    fn download(&self, path: &str, file: &Path, check: Check) -> Result<bool,Box<dyn Error + Send + Sync>> {
+	if fetch::mode().is_offline() {
+	    let name = file.file_name().unwrap().to_string_lossy().to_string();
+	    return Err(Box::new(CacheError{name}));
+	}
+	if let Some(dir) = file.parent() {
+	    fs::create_dir_all(dir)?;
+	}
+	let mut failure = None;
+	for base in &self.urls {
+	    let url = base.join(path)?;
+	    match self.fetcher.download(&url,file,&|p| check(&url,p)) {
+		Ok(true) => { return Ok(true); }
+		Ok(false) => {}
+		Err(e) => {
+		    warn!("Downloading {} ({})",url.as_str(),e);
+		    failure = Some(e);
+		}
+	    }
+	}
+	// Report failure only if no repository had the file
+	match failure {
+	    Some(e) => Err(e),
+	    None => Ok(false)
+	}
+    }
+}
+
+/// Determine the file recording a given kind of checksum for a given
+/// cached file (e.g. `wyc-0.10.18.jar.sha1`).
+pub fn checksum_path(jar: &Path, ext: &str) -> PathBuf {
+    let mut p = jar.as_os_str().to_owned();
+    p.push(".");
+    p.push(ext);
+    PathBuf::from(p)
+}
+
+/// Extract the digest from a checksum file.  These contain the digest
+/// in hex, which may be followed by the file name.
+fn parse_checksum(contents: &[u8]) -> String {
+    let s = String::from_utf8_lossy(contents);
+    s.split_whitespace().next().unwrap_or("").to_lowercase()
+}
+
+/// Check a cached file (e.g. a jar) against the checksum recorded
+/// alongside it (if any).  A file without a recorded checksum is
+/// assumed valid.
+pub fn verify_cached(jar: &Path) -> Result<bool,Box<dyn Error + Send + Sync>> {
+    for (ext,digest) in CHECKSUMS {
+	let path = checksum_path(jar,ext);
+	if path.exists() {
+	    let expected = parse_checksum(&fs::read(&path)?);
+	    return Ok(digest(&fs::read(jar)?) == expected);
+	}
+    }
+    Ok(true)
 }
 
 // ================================================================
-// Resolution Error
+// POM
 // ================================================================
 
-#[derive(Clone)]
-struct ResolutionError {
-    name: String
+/// A dependency listed in a POM file.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct PomDependency {
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: Option<String>,
+    pub scope: Option<String>,
+    pub kind: Option<String>,
+    pub classifier: Option<String>,
+    pub optional: bool,
+    /// Artifacts excluded from the dependencies of this dependency
+    /// (e.g. `org.whiley:jmodelgen` or `org.whiley:*`).
+    pub exclusions: Vec<String>
 }
 
-impl fmt::Display for ResolutionError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "failed resolving maven package {}",self.name)
+impl PomDependency {
+    /// Determine whether this dependency is needed on the classpath of
+    /// anything depending on the artifact which declares it.
+    fn is_transitive(&self) -> bool {
+	let scope = matches!(self.scope.as_deref(),None | Some("compile") | Some("runtime"));
+	let jar = matches!(self.kind.as_deref(),None | Some("jar"));
+	scope && jar && self.classifier.is_none() && !self.optional
+    }
+
+    /// Determine whether this dependency is excluded by a given
+    /// exclusion.
+    fn is_excluded_by(&self, exclusion: &str) -> bool {
+	let (g,a) = exclusion.split_once(':').unwrap_or((exclusion,"*"));
+	(g == "*" || g == self.group_id) && (a == "*" || a == self.artifact_id)
     }
 }
 
-impl fmt::Debug for ResolutionError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "failed resolving maven package {}",self.name)
+/// The parts of a Maven POM file relevant for determining the
+/// dependencies of an artifact.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Pom {
+    pub group_id: Option<String>,
+    pub artifact_id: Option<String>,
+    pub version: Option<String>,
+    pub parent: Option<MavenArtifact>,
+    pub properties: HashMap<String,String>,
+    /// Dependencies listed in the `dependencyManagement` section,
+    /// which determine versions for dependencies which omit them.
+    pub management: Vec<PomDependency>,
+    pub dependencies: Vec<PomDependency>
+}
+
+impl Pom {
+    /// Parse a given string into a POM, returning `None` if it is not
+    /// valid.
+    pub fn parse(contents: &str) -> Option<Pom> {
+	let doc = Document::parse(contents).ok()?;
+	let project = doc.root_element();
+	if project.tag_name().name() != "project" {
+	    return None;
+	}
+	let mut pom = Pom{
+	    group_id: child_text(project,"groupId"),
+	    artifact_id: child_text(project,"artifactId"),
+	    version: child_text(project,"version"),
+	    ..Default::default()
+	};
+	if let Some(p) = child(project,"parent") {
+	    let g = child_text(p,"groupId")?;
+	    let a = child_text(p,"artifactId")?;
+	    let v = child_text(p,"version")?;
+	    pom.parent = Some(MavenArtifact::from_parts(&g,&a,&v));
+	}
+	if let Some(ps) = child(project,"properties") {
+	    for p in ps.children().filter(Node::is_element) {
+		let value = p.text().unwrap_or("").trim().to_string();
+		pom.properties.insert(p.tag_name().name().to_string(),value);
+	    }
+	}
+	if let Some(m) = child(project,"dependencyManagement") {
+	    pom.management = parse_dependencies(m)?;
+	}
+	pom.dependencies = parse_dependencies(project)?;
+	Some(pom)
+    }
+
+    /// Inherit coordinates, properties and managed dependencies from a
+    /// given (effective) parent POM.
+    fn inherit(&mut self, parent: &Pom) {
+	if self.group_id.is_none() {
+	    self.group_id = parent.group_id.clone();
+	}
+	if self.version.is_none() {
+	    self.version = parent.version.clone();
+	}
+	for (k,v) in &parent.properties {
+	    self.properties.entry(k.clone()).or_insert_with(|| v.clone());
+	}
+	if let Some(p) = &self.parent {
+	    self.properties.entry("project.parent.groupId".to_string()).or_insert_with(|| p.group_id.clone());
+	    self.properties.entry("project.parent.version".to_string()).or_insert_with(|| p.version.clone());
+	}
+	self.management.extend(parent.management.iter().cloned());
+	self.dependencies.extend(parent.dependencies.iter().cloned());
+    }
+
+    /// Expand any property references (e.g. `${project.version}`)
+    /// used in dependencies.
+    fn interpolate(&mut self) {
+	let mut props = self.properties.clone();
+	for (k,v) in [("groupId",&self.group_id),("artifactId",&self.artifact_id),("version",&self.version)] {
+	    if let Some(v) = v {
+		props.insert(format!("project.{}",k),v.clone());
+		props.insert(format!("pom.{}",k),v.clone());
+	    }
+	}
+	for d in self.management.iter_mut().chain(self.dependencies.iter_mut()) {
+	    d.group_id = expand(&d.group_id,&props);
+	    d.artifact_id = expand(&d.artifact_id,&props);
+	    d.version = d.version.as_ref().map(|v| expand(v,&props));
+	    d.scope = d.scope.as_ref().map(|s| expand(s,&props));
+	}
+    }
+
+    /// Determine the version of a given dependency, which is either
+    /// given explicitly or by the `dependencyManagement` section.
+    fn version_of(&self, dep: &PomDependency) -> Option<String> {
+	if dep.version.is_some() {
+	    return dep.version.clone();
+	}
+	let m = self.management.iter().find(|m| m.group_id == dep.group_id && m.artifact_id == dep.artifact_id)?;
+	m.version.clone()
+    }
+}
+
+/// Parse the `dependencies` section (if any) within a given element.
+fn parse_dependencies(node: Node) -> Option<Vec<PomDependency>> {
+    let mut deps = Vec::new();
+    if let Some(ds) = child(node,"dependencies") {
+	for d in ds.children().filter(|n| n.tag_name().name() == "dependency") {
+	    let mut exclusions = Vec::new();
+	    if let Some(es) = child(d,"exclusions") {
+		for e in es.children().filter(|n| n.tag_name().name() == "exclusion") {
+		    let g = child_text(e,"groupId").unwrap_or("*".to_string());
+		    let a = child_text(e,"artifactId").unwrap_or("*".to_string());
+		    exclusions.push(format!("{}:{}",g,a));
+		}
+	    }
+	    deps.push(PomDependency{
+		group_id: child_text(d,"groupId")?,
+		artifact_id: child_text(d,"artifactId")?,
+		version: child_text(d,"version"),
+		scope: child_text(d,"scope"),
+		kind: child_text(d,"type"),
+		classifier: child_text(d,"classifier"),
+		optional: child_text(d,"optional").as_deref() == Some("true"),
+		exclusions
+	    });
+	}
+    }
+    Some(deps)
+}
+
+/// Find the first child element with a given name.
+fn child<'a,'b>(node: Node<'a,'b>, name: &str) -> Option<Node<'a,'b>> {
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+/// Extract the text of the first child element with a given name.
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node,name).and_then(|n| n.text()).map(|t| t.trim().to_string())
+}
+
+/// Expand property references (e.g. `${wyc.version}`) in a given
+/// string.  Unknown properties are left as is.
+fn expand(value: &str, properties: &HashMap<String,String>) -> String {
+    let mut result = value.to_string();
+    // NOTE: bounded to protect against recursive properties
+    for _ in 0..MAX_PARENTS {
+	let start = match result.find("${") {
+	    Some(i) => i,
+	    None => break
+	};
+	let end = match result[start..].find('}') {
+	    Some(i) => start + i,
+	    None => break
+	};
+	match properties.get(&result[start+2..end]) {
+	    Some(v) => result.replace_range(start..=end,v),
+	    None => break
+	}
     }
+    result
 }
 
-impl Error for ResolutionError {}
+// ================================================================
+// Tests
+// ================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{Body,FetchError,MemoryTransport,Transport};
+
+    const REPOSITORY : &str = "https://repo.test/";
+
+    /// The POM published for the WhileyCompiler (abridged), which
+    /// declares its dependency on `jmodelgen`.
+    const WYC_POM : &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project xmlns="http://maven.apache.org/POM/4.0.0" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/xsd/maven-4.0.0.xsd">
+  <modelVersion>4.0.0</modelVersion>
+  <groupId>org.whiley</groupId>
+  <artifactId>wyc</artifactId>
+  <version>0.10.18</version>
+  <packaging>jar</packaging>
+  <name>wyc</name>
+  <dependencies>
+    <dependency>
+      <groupId>org.whiley</groupId>
+      <artifactId>jmodelgen</artifactId>
+      <version>0.4.3</version>
+    </dependency>
+    <dependency>
+      <groupId>junit</groupId>
+      <artifactId>junit</artifactId>
+      <version>4.13.2</version>
+      <scope>test</scope>
+    </dependency>
+  </dependencies>
+</project>"#;
+
+    /// Construct a minimal POM for a given artifact with a given
+    /// body (e.g. dependencies).
+    fn pom(coordinate: &str, body: &str) -> String {
+        let a = artifact(coordinate);
+        format!("<project><groupId>{}</groupId><artifactId>{}</artifactId><version>{}</version>{}</project>",a.group_id,a.artifact_id,a.version,body)
+    }
+
+    /// Construct a dependency element on a given artifact.
+    fn dependency(coordinate: &str, extra: &str) -> String {
+        let a = artifact(coordinate);
+        format!("<dependency><groupId>{}</groupId><artifactId>{}</artifactId><version>{}</version>{}</dependency>",a.group_id,a.artifact_id,a.version,extra)
+    }
+
+    fn artifact(coordinate: &str) -> MavenArtifact {
+        MavenArtifact::new(coordinate).unwrap()
+    }
+
+    /// Construct a resolver for a repository holding the given POMs.
+    fn resolver(name: &str, poms: &[String]) -> MavenResolver<PathBuf> {
+        let mut transport = MemoryTransport::default();
+        for p in poms {
+            let pom = Pom::parse(p).unwrap();
+            let a = MavenArtifact::from_parts(pom.group_id.as_ref().unwrap(),pom.artifact_id.as_ref().unwrap(),pom.version.as_ref().unwrap());
+            transport.insert(&format!("{}{}",REPOSITORY,a.to_pompath()),p.as_bytes().to_vec());
+        }
+        let fetcher = Fetcher::new(vec![Box::new(transport)]);
+        let url = Url::parse(REPOSITORY).unwrap();
+        MavenResolver::with_fetcher(util::test_dir(name),vec![url],fetcher).unwrap()
+    }
+
+    fn resolve(resolver: &MavenResolver<PathBuf>, coordinates: &[&str]) -> Vec<String> {
+        let artifacts : Vec<MavenArtifact> = coordinates.iter().map(|c| artifact(c)).collect();
+        resolver.resolve(&artifacts).unwrap().iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_pom() {
+        let pom = Pom::parse(WYC_POM).unwrap();
+        assert_eq!(pom.group_id.as_deref(),Some("org.whiley"));
+        assert_eq!(pom.artifact_id.as_deref(),Some("wyc"));
+        assert_eq!(pom.version.as_deref(),Some("0.10.18"));
+        assert_eq!(pom.parent,None);
+        assert_eq!(pom.dependencies.len(),2);
+        let junit = &pom.dependencies[1];
+        assert_eq!(junit.scope.as_deref(),Some("test"));
+        assert!(!junit.is_transitive());
+    }
+
+    #[test]
+    fn parse_pom_dependencies() {
+        let body = format!("<dependencies>{}{}{}</dependencies>",
+                           dependency("org.a:opt:1.0","<optional>true</optional>"),
+                           dependency("org.a:doc:1.0","<classifier>javadoc</classifier>"),
+                           dependency("org.a:lib:1.0","<exclusions><exclusion><groupId>org.b</groupId><artifactId>x</artifactId></exclusion><exclusion><groupId>org.c</groupId></exclusion></exclusions>"));
+        let pom = Pom::parse(&pom("org.a:app:1.0",&body)).unwrap();
+        assert!(!pom.dependencies[0].is_transitive());
+        assert!(!pom.dependencies[1].is_transitive());
+        let lib = &pom.dependencies[2];
+        assert!(lib.is_transitive());
+        assert_eq!(lib.exclusions,vec!["org.b:x","org.c:*"]);
+    }
+
+    #[test]
+    fn parse_invalid_pom() {
+        assert_eq!(Pom::parse("<project>"),None);
+        assert_eq!(Pom::parse("<settings/>"),None);
+        // Dependencies must identify what they depend on
+        assert_eq!(Pom::parse("<project><dependencies><dependency><version>1.0</version></dependency></dependencies></project>"),None);
+    }
+
+    #[test]
+    fn wyc_declares_jmodelgen() {
+        // NOTE: whiley::MAVEN_DEPS relies on this
+        let r = resolver("wyc_declares_jmodelgen",&[WYC_POM.to_string(),pom("org.whiley:jmodelgen:0.4.3","")]);
+        assert_eq!(resolve(&r,&["org.whiley:wyc:0.10.18"]),vec!["org.whiley:wyc:0.10.18","org.whiley:jmodelgen:0.4.3"]);
+    }
+
+    #[test]
+    fn parent_inheritance() {
+        let parent = pom("org.a:parent:2.0","<properties><lib.version>1.5</lib.version></properties><dependencyManagement><dependencies><dependency><groupId>org.a</groupId><artifactId>lib</artifactId><version>${lib.version}</version></dependency></dependencies></dependencyManagement><dependencies><dependency><groupId>org.a</groupId><artifactId>log</artifactId><version>3.0</version></dependency></dependencies>");
+        let child = "<project><parent><groupId>org.a</groupId><artifactId>parent</artifactId><version>2.0</version></parent><groupId>org.a</groupId><artifactId>app</artifactId><version>1.0</version><dependencies><dependency><groupId>org.a</groupId><artifactId>lib</artifactId></dependency></dependencies></project>".to_string();
+        let r = resolver("parent_inheritance",&[parent,child]);
+        let pom = r.pom(&artifact("org.a:app:1.0")).unwrap().unwrap();
+        assert_eq!(pom.properties["lib.version"],"1.5");
+        assert_eq!(pom.version_of(&pom.dependencies[0]).as_deref(),Some("1.5"));
+        assert_eq!(resolve(&r,&["org.a:app:1.0"]),vec!["org.a:app:1.0","org.a:lib:1.5","org.a:log:3.0"]);
+    }
+
+    #[test]
+    fn missing_parent() {
+        let child = "<project><parent><groupId>org.a</groupId><artifactId>parent</artifactId><version>2.0</version></parent><groupId>org.a</groupId><artifactId>app</artifactId><version>1.0</version></project>".to_string();
+        let r = resolver("missing_parent",&[child]);
+        assert!(r.pom(&artifact("org.a:app:1.0")).is_err());
+    }
+
+    #[test]
+    fn property_interpolation() {
+        let body = "<properties><lib>library</lib></properties><dependencies><dependency><groupId>${project.groupId}</groupId><artifactId>${lib}</artifactId><version>${project.version}</version><scope>${unknown}</scope></dependency></dependencies>";
+        let r = resolver("property_interpolation",&[pom("org.a:app:1.0",body)]);
+        let pom = r.pom(&artifact("org.a:app:1.0")).unwrap().unwrap();
+        let d = &pom.dependencies[0];
+        assert_eq!(d.group_id,"org.a");
+        assert_eq!(d.artifact_id,"library");
+        assert_eq!(d.version.as_deref(),Some("1.0"));
+        // Unknown properties are left as is
+        assert_eq!(d.scope.as_deref(),Some("${unknown}"));
+    }
+
+    #[test]
+    fn transitive_exclusions() {
+        let app = pom("org.a:app:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:lib:1.0","<exclusions><exclusion><groupId>org.b</groupId><artifactId>*</artifactId></exclusion></exclusions>")));
+        let lib = pom("org.a:lib:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:util:1.0","")));
+        let util = pom("org.a:util:1.0",&format!("<dependencies>{}{}</dependencies>",dependency("org.b:x:1.0",""),dependency("org.c:y:1.0","")));
+        let r = resolver("transitive_exclusions",&[app,lib,util]);
+        // Exclusions apply to everything beneath the dependency
+        assert_eq!(resolve(&r,&["org.a:app:1.0"]),vec!["org.a:app:1.0","org.a:lib:1.0","org.a:util:1.0","org.c:y:1.0"]);
+    }
+
+    #[test]
+    fn nearest_wins() {
+        let app = pom("org.a:app:1.0",&format!("<dependencies>{}{}</dependencies>",dependency("org.a:mid:1.0",""),dependency("org.a:lib:1.0","")));
+        let mid = pom("org.a:mid:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:lib:2.0","")));
+        let r = resolver("nearest_wins",&[app,mid,pom("org.a:lib:1.0",""),pom("org.a:lib:2.0","")]);
+        assert_eq!(resolve(&r,&["org.a:app:1.0"]),vec!["org.a:app:1.0","org.a:mid:1.0","org.a:lib:1.0"]);
+    }
+
+    #[test]
+    fn first_declaration_wins() {
+        let one = pom("org.a:one:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:lib:1.0","")));
+        let two = pom("org.a:two:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:lib:2.0","")));
+        let r = resolver("first_declaration_wins",&[one,two,pom("org.a:lib:1.0",""),pom("org.a:lib:2.0","")]);
+        assert_eq!(resolve(&r,&["org.a:two:1.0","org.a:one:1.0"]),vec!["org.a:two:1.0","org.a:one:1.0","org.a:lib:2.0"]);
+    }
+
+    #[test]
+    fn missing_pom() {
+        // Dependencies without a POM have none themselves
+        let app = pom("org.a:app:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:lib:1.0","")));
+        let r = resolver("missing_pom",&[app]);
+        assert_eq!(resolve(&r,&["org.a:app:1.0"]),vec!["org.a:app:1.0","org.a:lib:1.0"]);
+        // Though what is asked for must have one
+        let err = r.resolve(&[artifact("org.a:lib:1.0")]).unwrap_err();
+        assert_eq!(err.to_string(),"failed resolving org.a:lib:1.0 (no POM found)");
+    }
+
+    #[test]
+    fn group_layout() {
+        // Artifacts are cached by group, as in the repository
+        let a = pom("org.a:lib:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.a:x:1.0","")));
+        let b = pom("org.b:lib:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.b:y:1.0","")));
+        let r = resolver("group_layout",&[a,b]);
+        assert_eq!(resolve(&r,&["org.a:lib:1.0"]),vec!["org.a:lib:1.0","org.a:x:1.0"]);
+        assert_eq!(resolve(&r,&["org.b:lib:1.0"]),vec!["org.b:lib:1.0","org.b:y:1.0"]);
+        assert!(r.dir.join("org/b/lib/1.0/lib-1.0.pom").exists());
+    }
+
+    /// Serves files from memory, except for checksums of a given kind
+    /// which are refused (as by some mirrors).
+    struct RefusingTransport {
+        ext: &'static str,
+        inner: MemoryTransport
+    }
+
+    impl Transport for RefusingTransport {
+        fn supports(&self, url: &Url) -> bool {
+            self.inner.supports(url)
+        }
+
+        fn open(&self, url: &Url, offset: u64) -> Result<Option<Body>,FetchError> {
+            if url.as_str().ends_with(self.ext) {
+                return Err(FetchError::new(url,&"403 Forbidden"));
+            }
+            self.inner.open(url,offset)
+        }
+    }
+
+    #[test]
+    fn checksum_fallback() {
+        let good = artifact("org.a:good:1.0");
+        let bad = artifact("org.a:bad:1.0");
+        let mut inner = MemoryTransport::default();
+        for a in [&good,&bad] {
+            let url = format!("{}{}",REPOSITORY,a.to_jarpath());
+            inner.insert(&url,b"jar".to_vec());
+            let sha1 = if a == &good { util::sha1_bytes(b"jar") } else { util::sha1_bytes(b"other") };
+            inner.insert(&format!("{}.sha1",url),sha1.into_bytes());
+        }
+        let transport = RefusingTransport{ext:".sha256",inner};
+        let fetcher = Fetcher::new(vec![Box::new(transport)]);
+        let url = Url::parse(REPOSITORY).unwrap();
+        let r = MavenResolver::with_fetcher(util::test_dir("checksum_fallback"),vec![url],fetcher).unwrap();
+        // The sha1 checksum is used instead of the refused sha256 one
+        let jar = r.get(&good).unwrap();
+        assert!(checksum_path(&jar,"sha1").exists());
+        assert!(r.get(&bad).is_err());
+    }
+
+    #[test]
+    fn overridden_pom() {
+        // The POMs of overridden artifacts are not downloaded
+        let poms = [pom("org.a:app:1.0",&format!("<dependencies>{}</dependencies>",dependency("org.b:lib:2.0","")))];
+        let app = artifact("org.a:app:1.0");
+        let path = PathBuf::from("/tmp/app/classes");
+        let r = resolver("overridden_pom",&poms).with_overrides(vec![Override{coordinate:"org.a:app".to_string(),path}]);
+        assert_eq!(resolve(&r,&["org.a:app:1.0"]),vec!["org.a:app:1.0"]);
+        assert!(r.find(&app.to_pompath()).is_none());
+        // Though any already cached are used
+        r.fetch(&app.to_pompath()).unwrap();
+        assert_eq!(resolve(&r,&["org.a:app:1.0"]),vec!["org.a:app:1.0","org.b:lib:2.0"]);
+    }
+}
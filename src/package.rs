@@ -1,24 +1,137 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::fs;
+use std::fs::File;
+use std::io::{Read,Seek};
 use std::path::{Path,PathBuf};
+use std::sync::Mutex;
 use log::{error,info};
-use reqwest;
 use reqwest::Url;
+use semver::{Op,Version,VersionReq};
+use zip::ZipArchive;
+use zip::result::ZipError;
+use crate::build::DEPENDENCIES;
+use crate::config;
+use crate::config::{Config,Key};
+use crate::fetch;
+use crate::fetch::{CacheError,Fetcher,ResolutionError};
+use crate::index;
+use crate::maven;
+use crate::index::{Index,IndexEntry};
+use crate::util;
+use crate::util::FileLock;
+
+/// Name of the build configuration file embedded within a package.
+const CONFIG_FILE : &str = "wy.toml";
+
+/// Maximum number of attempts at refining the selected versions
+/// before resolution is abandoned.
+const MAX_ITERATIONS : usize = 100;
 
 // ================================================================
 // Dependency
 // ================================================================
 
-#[derive(Clone)]
+/// A dependency on a given package, as specified in the
+/// `[dependencies]` section of a build configuration.  This
+/// identifies the range of versions which are acceptable using a
+/// (cargo-style) version requirement, such as `^0.3`, `~0.3.2`,
+/// `>=0.3, <0.5` or `*`.  Observe that a plain version (e.g. `0.3.2`)
+/// is treated as `^0.3.2`.
+#[derive(Clone,Debug,PartialEq)]
 pub struct Dependency {
     name: String,
-    version: String
+    requirement: VersionReq
 }
 
 impl Dependency {
-    pub fn new(name: String, version: String) -> Self {
-        Dependency{name,version}
+    pub fn new(name: String, requirement: VersionReq) -> Self {
+        Dependency{name,requirement}
+    }
+    /// Construct a dependency by parsing a given version requirement.
+    pub fn parse(name: String, requirement: &str) -> Result<Self,semver::Error> {
+        let requirement = VersionReq::parse(requirement)?;
+        Ok(Dependency{name,requirement})
+    }
+    /// Get the name of the package being depended upon.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Get the range of versions acceptable for this dependency.
+    pub fn requirement(&self) -> &VersionReq {
+        &self.requirement
+    }
+    /// Check whether a given package satisfies this dependency.
+    pub fn matches(&self, pkg: &Package) -> bool {
+        self.name == pkg.name && self.requirement.matches(&pkg.version)
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{} {}",self.name,self.requirement)
+    }
+}
+
+// ================================================================
+// Path Dependency
+// ================================================================
+
+/// A dependency on a package which lives in a local directory, as
+/// specified by a table in the `[dependencies]` section of a build
+/// configuration (e.g. `mylib = { path = "../mylib" }`).  Such
+/// packages are built from source, rather than being resolved from a
+/// registry.
+#[derive(Clone,Debug,PartialEq)]
+pub struct PathDependency {
+    name: String,
+    path: PathBuf
+}
+
+impl PathDependency {
+    pub fn new(name: String, path: PathBuf) -> Self {
+        PathDependency{name,path}
+    }
+    /// Get the name of the package being depended upon.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Get the directory containing the package being depended upon.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl fmt::Display for PathDependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{} ({})",self.name,self.path.display())
+    }
+}
+
+// ================================================================
+// Package
+// ================================================================
+
+/// A specific version of a given package, as determined by
+/// resolution.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Package {
+    name: String,
+    version: Version
+}
+
+impl Package {
+    pub fn new(name: String, version: Version) -> Self {
+        Package{name,version}
+    }
+    /// Get the name of this package.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Get the version of this package.
+    pub fn version(&self) -> &Version {
+        &self.version
     }
     pub fn to_zipname(&self) -> String {
         format!("{}-v{}.zip",self.name,self.version)
@@ -30,7 +143,7 @@ impl Dependency {
     }
 }
 
-impl fmt::Display for Dependency {
+impl fmt::Display for Package {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,"{}-{}",self.name,self.version)
     }
@@ -40,80 +153,705 @@ impl fmt::Display for Dependency {
 // Package Resolver
 // ================================================================
 
-#[derive(Clone,Debug,PartialEq)]
+/// A requirement placed on some package during resolution, along with
+/// the chain of packages which lead to it.
+type Requirement = (Dependency,Vec<Package>);
+
+/// The outcome of walking the dependency graph, consisting of the
+/// names of all packages encountered (in order), along with the
+/// requirements placed on each of them.
+type Walk = (Vec<String>,HashMap<String,Vec<Requirement>>);
+
 pub struct PackageResolver<T: AsRef<Path>> {
     /// Path to cache root on local filesyste
     dir: T,
-    /// Base URL for downloading packages
-    url: Url
+    /// Base URL for downloading packages (and their indices)
+    url: Url,
+    /// Responsible for downloading packages (and their indices)
+    fetcher: Fetcher,
+    /// Package indices downloaded so far, where `None` indicates a
+    /// package has no index.
+    indices: Mutex<HashMap<String,Option<Index>>>,
+    /// Read-only caches searched after this cache (e.g. one
+    /// provisioned by an administrator).  These are never written.
+    layers: Vec<PathBuf>,
+    /// Lock on the cache, held whilst this resolver exists
+    _lock: FileLock
 }
 
 impl<T: AsRef<Path>> PackageResolver<T> {
     /// Construct a package resolver which stores cached files in a
     /// given filesystem directory, and downloads them from a given
-    /// base URL.
-    pub fn new(dir: T, url: Url) -> Self {
-	// Ensure cache directory exists
-	fs::create_dir_all(dir.as_ref()).unwrap();
+    /// base URL.  This may refer to a remote server or to the local
+    /// filesystem (via `file://`).
+    pub fn new(dir: T, url: Url) -> Result<Self,Box<dyn Error>> {
+	PackageResolver::with_fetcher(dir,url,Fetcher::default())
+    }
+
+    /// Construct a package resolver which downloads files using a
+    /// given fetcher.  This blocks until no other process is using
+    /// the cache.
+    pub fn with_fetcher(dir: T, url: Url, fetcher: Fetcher) -> Result<Self,Box<dyn Error>> {
+	// Ensure cache directory exists, and lock it
+	let _lock = FileLock::acquire(dir.as_ref())?;
 	// Done
-	PackageResolver{dir,url}
+	Ok(PackageResolver{dir,url,fetcher,indices:Mutex::new(HashMap::new()),layers:Vec::new(),_lock})
+    }
+
+    /// Search a given set of read-only caches for packages not in
+    /// this cache.  These are searched in order.
+    pub fn with_layers(mut self, layers: Vec<PathBuf>) -> Self {
+	self.layers = layers;
+	self
     }
 
     /// Resolve a given set of dependencies.  This is a non-trivial
     /// process as we identify all transitive dependencies, and
     /// determine a coherent set which matches all versioning
-    /// constraints (if one exists).
-    pub fn resolve(&self, deps : &[Dependency]) -> Result<(),Box<dyn Error>> {
-        for dep in deps {
-            self.get(&dep)?;
+    /// constraints (if one exists).  For each package, the highest
+    /// version matching all requirements placed on it is chosen.  The
+    /// resolved packages are returned in the order they were first
+    /// encountered.
+    pub fn resolve(&self, deps : &[Dependency]) -> Result<Vec<Package>,Box<dyn Error>>
+    where T: Sync {
+        // Versions selected for each package so far
+        let mut selected : HashMap<String,Version> = HashMap::new();
+        // Versions selected on previous iterations
+        let mut seen : Vec<HashMap<String,Version>> = Vec::new();
+        //
+        loop {
+            // Walk dependency graph using versions selected so far
+            let (order,reqs) = self.walk(deps,&mut selected)?;
+            // Check for the same selection as before (i.e. a cycle),
+            // or giving up, as either means no coherent set will be
+            // found this way.
+            let start = match seen.iter().position(|s| *s == selected) {
+                Some(i) => Some(i),
+                None if seen.len() >= MAX_ITERATIONS => Some(0),
+                None => None
+            };
+            if let Some(i) = start {
+                // Report requirements on packages which kept changing
+                let names : Vec<String> = order.into_iter().filter(|n| seen[i..].iter().any(|s| s.get(n) != selected.get(n))).collect();
+                let requirements = names.iter().flat_map(|n| reqs[n].iter().cloned()).collect();
+                return Err(Box::new(InconsistentError{names,requirements}));
+            }
+            seen.push(selected.clone());
+            // Check selected versions satisfy all requirements
+            let mut changed = false;
+            for name in &order {
+                let rs = &reqs[name];
+                if rs.iter().all(|(d,_)| d.requirement.matches(&selected[name])) {
+                    continue;
+                }
+                // Attempt to select a version matching them all
+                match self.select(name,rs)? {
+                    Some(v) => {
+                        info!("Selected {}-{}",name,v);
+                        selected.insert(name.clone(),v);
+                        changed = true;
+                    }
+                    None => {
+                        return Err(Box::new(ConflictError{name:name.clone(),requirements:rs.clone()}));
+                    }
+                }
+            }
+            // Check whether a coherent set was found
+            if !changed {
+                return Ok(order.into_iter().map(|n| {
+                    let v = selected[&n].clone();
+                    Package::new(n,v)
+                }).collect());
+            }
+        }
+    }
+
+    /// Walk the dependency graph from a given set of (root)
+    /// dependencies, selecting versions for any packages which don't
+    /// yet have one.  The graph is walked one level at a time, such
+    /// that the indices and dependencies of all packages on a level
+    /// are fetched concurrently.
+    fn walk(&self, deps: &[Dependency], selected: &mut HashMap<String,Version>) -> Result<Walk,Box<dyn Error>>
+    where T: Sync {
+        let mut order = Vec::new();
+        let mut reqs : HashMap<String,Vec<Requirement>> = HashMap::new();
+        let mut level : Vec<Requirement> = deps.iter().map(|d| (d.clone(),Vec::new())).collect();
+        //
+        while !level.is_empty() {
+            // Fetch indices for packages not yet visited
+            let mut names : Vec<String> = Vec::new();
+            for (d,_) in &level {
+                if !reqs.contains_key(&d.name) && !names.contains(&d.name) {
+                    names.push(d.name.clone());
+                }
+            }
+            fetch::fetch_all(&names,|n| self.index(n)).map_err(|e| e as Box<dyn Error>)?;
+            // Select versions for packages not yet visited
+            let mut pkgs : Vec<(Package,Vec<Package>)> = Vec::new();
+            for (dep,chain) in level {
+                let name = dep.name.clone();
+                // Record requirement on this package
+                let rs = reqs.entry(name.clone()).or_default();
+                rs.push((dep,chain.clone()));
+                if rs.len() > 1 {
+                    // Already visited
+                    continue;
+                }
+                order.push(name.clone());
+                // Determine version to use
+                let version = match selected.get(&name) {
+                    Some(v) => v.clone(),
+                    None => {
+                        match self.select(&name,rs)? {
+                            Some(v) => {
+                                selected.insert(name.clone(),v.clone());
+                                v
+                            }
+                            None => {
+                                return Err(Box::new(ConflictError{name,requirements:rs.clone()}));
+                            }
+                        }
+                    }
+                };
+                pkgs.push((Package::new(name,version),chain));
+            }
+            // Determine dependencies of these packages
+            let visited : Vec<Package> = pkgs.iter().map(|(p,_)| p.clone()).collect();
+            let pdeps = fetch::fetch_all(&visited,|p| self.dependencies(p)).map_err(|e| e as Box<dyn Error>)?;
+            let mut next = Vec::new();
+            for ((pkg,chain),ds) in pkgs.into_iter().zip(pdeps) {
+                let mut nchain = chain;
+                nchain.push(pkg);
+                for d in ds {
+                    next.push((d,nchain.clone()));
+                }
+            }
+            level = next;
         }
         // Done
-        Ok(())
+        Ok((order,reqs))
     }
 
-    pub fn get<'b>(&self, dep: &Dependency) -> Result<PathBuf,Box<dyn Error>> {
-	// Determine dependency location
-	let mut zip = PathBuf::new();
-	zip.push(self.dir.as_ref());
-	zip.push(dep.to_zipname());
-	//
-	if !zip.as_path().exists() {
-	    // Cache miss, try to download
-	    let url = dep.to_url(&self.url);
-	    let response = reqwest::blocking::get(url.clone())?;
-            // Check status code
-            if response.status().is_success() {
+    /// Select the highest available version of a given package which
+    /// matches all of the given requirements (if one exists).
+    /// When the package has no index, only a limited set of versions
+    /// is known (see `unindexed()`).  Thus, failing to find a match
+    /// is reported as a missing index rather than a conflict.
+    fn select(&self, name: &str, reqs: &[Requirement]) -> Result<Option<Version>,Box<dyn Error>> {
+        let index = self.index(name).map_err(|e| e as Box<dyn Error>)?;
+        let versions = match &index {
+            Some(i) => i.versions(),
+            None => self.unindexed(name,reqs)
+        };
+        let version = versions.into_iter()
+            .filter(|v| reqs.iter().all(|(d,_)| d.requirement.matches(v)))
+            .max();
+        if version.is_none() && index.is_none() {
+            let requirements = reqs.iter().map(|(d,_)| d.to_string()).collect::<Vec<_>>().join(", ");
+            if fetch::mode().is_offline() {
+                return Err(Box::new(CacheError{name:requirements}));
+            }
+            let url = index::to_index_url(&self.url,name).to_string();
+            return Err(Box::new(IndexError{name:name.to_string(),url,requirements}));
+        }
+        Ok(version)
+    }
+
+    /// Determine the versions of a package without an index which
+    /// are available.  This includes any versions already in the
+    /// local cache, along with any version explicitly named in the
+    /// given requirements (e.g. `0.3.2` for `^0.3.2`).
+    fn unindexed(&self, name: &str, reqs: &[Requirement]) -> Vec<Version> {
+        let mut versions = Vec::new();
+        // Check the local cache (and any layers beneath it)
+        let prefix = format!("{}-v",name);
+        for dir in self.dirs() {
+            if let Ok(entries) = fs::read_dir(dir) {
+                for e in entries.flatten() {
+                    let filename = e.file_name();
+                    let filename = filename.to_string_lossy();
+                    if let Some(v) = filename.strip_prefix(&prefix).and_then(|s| s.strip_suffix(".zip")) {
+                        if let Ok(v) = Version::parse(v) {
+                            versions.push(v);
+                        }
+                    }
+                }
+            }
+        }
+        // Check explicitly named versions
+        for (d,_) in reqs {
+            for c in &d.requirement.comparators {
+                let exact = matches!(c.op,Op::Exact | Op::Caret | Op::Tilde | Op::GreaterEq);
+                if let (true,Some(minor),Some(patch)) = (exact,c.minor,c.patch) {
+                    let mut v = Version::new(c.major,minor,patch);
+                    v.pre = c.pre.clone();
+                    versions.push(v);
+                }
+            }
+        }
+        versions.sort();
+        versions.dedup();
+        versions
+    }
+
+    /// Get the index for a given package, or `None` if the package
+    /// has no index.  Indices are only downloaded once per resolver.
+    /// When offline, no index is available and, hence, only cached
+    /// packages are considered.
+    pub fn index(&self, name: &str) -> Result<Option<Index>,Box<dyn Error + Send + Sync>> {
+        if fetch::mode().is_offline() {
+            return Ok(None);
+        }
+        if let Some(i) = self.indices.lock().unwrap().get(name) {
+            return Ok(i.clone());
+        }
+        let url = index::to_index_url(&self.url,name);
+        let index = match self.fetcher.get(&url)? {
+            Some(bytes) => {
                 info!("Downloaded {}",url.as_str());
-	        fs::write(zip.as_path(),response.bytes()?)?;
-            } else {
-                error!("Downloading {} ({:?})",url.as_str(),response.status());
-                return Err(Box::new(ResolutionError{dep:(*dep).clone()}));
+                Some(String::from_utf8(bytes)?.parse::<Index>()?)
+            }
+            None => None
+        };
+        self.indices.lock().unwrap().insert(name.to_string(),index.clone());
+        Ok(index)
+    }
+
+    /// Get the index entry for a given package (if it exists).
+    fn entry(&self, pkg: &Package) -> Result<Option<IndexEntry>,Box<dyn Error + Send + Sync>> {
+        let index = self.index(&pkg.name)?;
+        Ok(index.and_then(|i| i.entry(&pkg.version).cloned()))
+    }
+
+    /// Determine the dependencies of a given package.  These are
+    /// taken from the package's index entry where possible, otherwise
+    /// the package itself is downloaded to find them.
+    pub fn dependencies(&self, pkg: &Package) -> Result<Vec<Dependency>,Box<dyn Error + Send + Sync>> {
+        match self.entry(pkg)? {
+            Some(e) => Ok(e.dependencies),
+            None => {
+                let zip = self.get(pkg)?;
+                read_dependencies(&zip)
             }
+        }
+    }
+
+    /// Determine the location of a given package within the local
+    /// cache.  Observe that there is no guarantee the package is
+    /// actually present in the cache.
+    pub fn path(&self, pkg: &Package) -> PathBuf {
+	let mut zip = PathBuf::new();
+	zip.push(self.dir.as_ref());
+	zip.push(pkg.to_zipname());
+        zip
+    }
+
+    /// Locate a given package in the local cache or, failing that, in
+    /// one of the read-only layers beneath it.
+    pub fn find(&self, pkg: &Package) -> Option<PathBuf> {
+        self.dirs().into_iter().map(|d| d.join(pkg.to_zipname())).find(|z| z.exists())
+    }
+
+    /// Get the local cache followed by the layers beneath it.
+    fn dirs(&self) -> Vec<&Path> {
+        let mut dirs = vec![self.dir.as_ref()];
+        dirs.extend(self.layers.iter().map(PathBuf::as_path));
+        dirs
+    }
+
+    /// Determine the URL from which a given package is downloaded.
+    pub fn url(&self, pkg: &Package) -> Url {
+        pkg.to_url(&self.url)
+    }
+
+    pub fn get<'b>(&self, pkg: &Package) -> Result<PathBuf,Box<dyn Error + Send + Sync>> {
+	// Determine package location
+	if let Some(zip) = self.find(pkg) {
+	    return Ok(zip);
+	}
+	// Cache miss, try to download
+	let zip = self.path(pkg);
+	if fetch::mode().is_offline() {
+	    return Err(Box::new(CacheError{name:pkg.to_zipname()}));
+	}
+	let url = self.url(pkg);
+	let entry = self.entry(pkg)?;
+	// Check against index (if applicable)
+	let check = |p: &Path| -> Result<(),Box<dyn Error + Send + Sync>> {
+	    let checksum = util::sha256(p)?;
+	    if let Some(e) = &entry {
+		if e.checksum != checksum {
+		    error!("Downloading {} (checksum mismatch)",url.as_str());
+		    return Err(Box::new(ResolutionError{name:pkg.to_string()}));
+		}
+	    }
+	    // Record checksum for checking cached package later
+	    fs::write(maven::checksum_path(&zip,"sha256"),checksum)?;
+	    Ok(())
+	};
+	if !self.fetcher.download(&url,&zip,&check)? {
+	    error!("Downloading {} (not found)",url.as_str());
+	    return Err(Box::new(ResolutionError{name:pkg.to_string()}));
 	}
 	//
 	Ok(zip)
     }
+
+    /// Get the zip files for a given set of packages, downloading
+    /// those not already cached concurrently.
+    pub fn get_all(&self, pkgs: &[Package]) -> Result<Vec<PathBuf>,Box<dyn Error>>
+    where T: Sync {
+        let missing : Vec<Package> = pkgs.iter().filter(|p| self.find(p).is_none()).cloned().collect();
+        fetch::fetch_all(&missing,|p| self.get(p)).map_err(|e| e as Box<dyn Error>)?;
+        pkgs.iter().map(|p| self.get(p).map_err(|e| e as Box<dyn Error>)).collect()
+    }
+}
+
+/// Read the dependencies of a given package from the build
+/// configuration embedded in its zip file.  Packages which do not
+/// include a build configuration (or whose configuration has no
+/// `[dependencies]` section) are assumed to have no dependencies.
+/// Path dependencies cannot be resolved and, hence, are rejected.
+fn read_dependencies(zip: &Path) -> Result<Vec<Dependency>,Box<dyn Error + Send + Sync>> {
+    read_dependencies_from(File::open(zip)?,&zip.display().to_string())
 }
 
+/// Read the dependencies of a package, as for `read_dependencies()`,
+/// from the contents of its zip file (e.g. as downloaded).  The
+/// location given identifies the package when reporting errors.
+pub fn read_dependencies_from<R: Read + Seek>(zip: R, location: &str) -> Result<Vec<Dependency>,Box<dyn Error + Send + Sync>> {
+    let mut archive = ZipArchive::new(zip)?;
+    // Extract build configuration
+    let mut contents = String::new();
+    match archive.by_name(CONFIG_FILE) {
+        Ok(mut f) => { f.read_to_string(&mut contents)?; }
+        Err(ZipError::FileNotFound) => { return Ok(Vec::new()); }
+        Err(e) => { return Err(Box::new(e)); }
+    }
+    let err = |message: String| DependencyError{location:location.to_string(),message};
+    // Parse configuration
+    let config = Config::from_str(contents.as_str()).map_err(|e| err(format!("{:?}",e)))?;
+    let mut deps = Vec::new();
+    if !config.contains(&DEPENDENCIES) {
+        return Ok(deps);
+    }
+    for k in config.find_keys(&DEPENDENCIES).map_err(|e| err(format!("{:?}",e)))? {
+        let key = ["dependencies",k.as_str()];
+        match config.get_string(&Key::new(&key)) {
+            Ok(v) => {
+                let d = Dependency::parse(k.clone(),&v).map_err(|_| err(format!("invalid version requirement \"{}\" for {}",v,k)))?;
+                deps.push(d);
+            }
+            Err(config::Error::Expected(_,_)) => {
+                return Err(Box::new(err(format!("unsupported path dependency {}",k))));
+            }
+            Err(e) => { return Err(Box::new(err(format!("{:?}",e)))); }
+        }
+    }
+    // Done
+    Ok(deps)
+}
+
+// ================================================================
+// Dependency Error
+// ================================================================
+
+/// Indicates the dependencies of a package (i.e. zip file) could not
+/// be determined from its build configuration.
+#[derive(Clone)]
+struct DependencyError {
+    location: String,
+    message: String
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid dependencies in {} ({})",self.location,self.message)
+    }
+}
+
+impl fmt::Debug for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self,f)
+    }
+}
+
+impl Error for DependencyError {}
+
 // ================================================================
-// Resolution Error
+// Index Error
 // ================================================================
 
+/// Indicates a package has no index in the registry, and no version
+/// in the local cache (or explicitly named) satisfies the
+/// requirements placed on it.
 #[derive(Clone)]
-struct ResolutionError {
-    dep: Dependency
+struct IndexError {
+    name: String,
+    url: String,
+    requirements: String
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no index for package {} at {} (and no cached version satisfies {})",self.name,self.url,self.requirements)
+    }
+}
+
+impl fmt::Debug for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self,f)
+    }
+}
+
+impl Error for IndexError {}
+
+// ================================================================
+// Conflict Error
+// ================================================================
+
+/// Indicates no available version of a package satisfies all of the
+/// requirements placed on it.  Each requirement is recorded along
+/// with the chain of packages which lead to it.
+#[derive(Clone)]
+struct ConflictError {
+    name: String,
+    requirements: Vec<Requirement>
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no version of package {} satisfies all requirements:",self.name)?;
+        write_requirements(f,&self.requirements)
+    }
+}
+
+impl fmt::Debug for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self,f)
+    }
+}
+
+impl Error for ConflictError {}
+
+/// Write out a given set of requirements, one per line, along with
+/// the chain of packages leading to each.
+fn write_requirements(f: &mut fmt::Formatter, requirements: &[Requirement]) -> fmt::Result {
+    for (d,chain) in requirements {
+        write!(f,"\n  ")?;
+        if chain.is_empty() {
+            write!(f,"wy.toml")?;
+        }
+        for (i,p) in chain.iter().enumerate() {
+            if i > 0 {
+                write!(f," -> ")?;
+            }
+            write!(f,"{}",p)?;
+        }
+        write!(f," requires {}",d)?;
+    }
+    Ok(())
+}
+
+/// Indicates no set of versions for some packages satisfies all the
+/// requirements on them, though some version of each satisfies the
+/// requirements on it alone.  That is, selecting a version for one
+/// changes the requirements on another.
+struct InconsistentError {
+    names: Vec<String>,
+    requirements: Vec<Requirement>
 }
 
-impl fmt::Display for ResolutionError {
+impl fmt::Display for InconsistentError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "failed resolving package {}",self.dep)
+        write!(f, "no versions of packages {} satisfy all requirements:",self.names.join(", "))?;
+        write_requirements(f,&self.requirements)
     }
 }
 
-impl fmt::Debug for ResolutionError {
+impl fmt::Debug for InconsistentError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "failed resolving package {}",self.dep)
+        fmt::Display::fmt(self,f)
     }
 }
 
-impl Error for ResolutionError {}
+impl Error for InconsistentError {}
+
+// ================================================================
+// Tests
+// ================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::MemoryTransport;
+
+    const REGISTRY : &str = "https://registry.test/";
+
+    /// Construct a resolver for a registry holding the given package
+    /// indices.
+    fn resolver(name: &str, indices: &[(&str,&str)]) -> PackageResolver<PathBuf> {
+        let mut transport = MemoryTransport::default();
+        for (pkg,index) in indices {
+            transport.insert(&format!("{}{}/index.toml",REGISTRY,pkg),index.as_bytes().to_vec());
+        }
+        let fetcher = Fetcher::new(vec![Box::new(transport)]);
+        let url = Url::parse(REGISTRY).unwrap();
+        PackageResolver::with_fetcher(util::test_dir(name),url,fetcher).unwrap()
+    }
+
+    /// Construct an index entry for a given version with given
+    /// dependencies.
+    fn entry(version: &str, deps: &[(&str,&str)]) -> String {
+        let mut s = format!("[[version]]\nversion = \"{}\"\nchecksum = \"00\"\n",version);
+        if !deps.is_empty() {
+            s.push_str("[version.dependencies]\n");
+            for (n,r) in deps {
+                s.push_str(&format!("{} = \"{}\"\n",n,r));
+            }
+        }
+        s
+    }
+
+    fn dep(name: &str, req: &str) -> Dependency {
+        Dependency::parse(name.to_string(),req).unwrap()
+    }
+
+    fn resolved(pkgs: &[Package]) -> Vec<String> {
+        pkgs.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn std_index() -> String {
+        ["0.2.9","0.3.0","0.3.2","0.3.7","0.4.1","1.0.0"].iter().map(|v| entry(v,&[])).collect()
+    }
+
+    #[test]
+    fn select_caret() {
+        let r = resolver("select_caret",&[("std",&std_index())]);
+        assert_eq!(resolved(&r.resolve(&[dep("std","^0.3")]).unwrap()),vec!["std-0.3.7"]);
+        // Plain versions are treated as caret requirements
+        assert_eq!(resolved(&r.resolve(&[dep("std","0.3.2")]).unwrap()),vec!["std-0.3.7"]);
+    }
+
+    #[test]
+    fn select_tilde() {
+        let r = resolver("select_tilde",&[("std",&std_index())]);
+        assert_eq!(resolved(&r.resolve(&[dep("std","~0.3.0")]).unwrap()),vec!["std-0.3.7"]);
+        assert_eq!(resolved(&r.resolve(&[dep("std","~0.2")]).unwrap()),vec!["std-0.2.9"]);
+    }
+
+    #[test]
+    fn select_range() {
+        let r = resolver("select_range",&[("std",&std_index())]);
+        assert_eq!(resolved(&r.resolve(&[dep("std",">=0.3, <0.4")]).unwrap()),vec!["std-0.3.7"]);
+        assert_eq!(resolved(&r.resolve(&[dep("std","<0.3.2")]).unwrap()),vec!["std-0.3.0"]);
+        assert_eq!(resolved(&r.resolve(&[dep("std","*")]).unwrap()),vec!["std-1.0.0"]);
+    }
+
+    #[test]
+    fn select_highest_match() {
+        // Every requirement on a package must be satisfied
+        let app = entry("1.0.0",&[("std","^0.3"),("math","*")]);
+        let math = entry("0.1.0",&[("std","<0.3.5")]);
+        let r = resolver("select_highest_match",&[("std",&std_index()),("app",&app),("math",&math)]);
+        let pkgs = r.resolve(&[dep("app","*")]).unwrap();
+        assert_eq!(resolved(&pkgs),vec!["app-1.0.0","math-0.1.0","std-0.3.2"]);
+    }
+
+    #[test]
+    fn select_ignores_yanked() {
+        let index = format!("{}{}yanked = true\n",std_index(),entry("0.3.9",&[]));
+        let r = resolver("select_ignores_yanked",&[("std",&index)]);
+        assert_eq!(resolved(&r.resolve(&[dep("std","^0.3")]).unwrap()),vec!["std-0.3.7"]);
+    }
+
+    #[test]
+    fn conflicting_chains() {
+        let app = entry("1.0.0",&[("std","^0.3"),("math","*")]);
+        let math = entry("0.1.0",&[("std","^0.4")]);
+        let r = resolver("conflicting_chains",&[("std",&std_index()),("app",&app),("math",&math)]);
+        let err = r.resolve(&[dep("app","*")]).unwrap_err().to_string();
+        assert_eq!(err,"no version of package std satisfies all requirements:\n  app-1.0.0 requires std ^0.3\n  app-1.0.0 -> math-0.1.0 requires std ^0.4");
+    }
+
+    #[test]
+    fn inconsistent_cycle() {
+        // Each version of a and b requires the other version of the other
+        let app = entry("1.0.0",&[("a","*"),("b","*")]);
+        let a = format!("{}{}",entry("1.0.0",&[("b","^2")]),entry("2.0.0",&[("b","^1")]));
+        let b = format!("{}{}",entry("1.0.0",&[("a","^1")]),entry("2.0.0",&[("a","^2")]));
+        let r = resolver("inconsistent_cycle",&[("app",&app),("a",&a),("b",&b)]);
+        let err = r.resolve(&[dep("app","*")]).unwrap_err().to_string();
+        assert_eq!(err,"no versions of packages a, b satisfy all requirements:\n  app-1.0.0 requires a *\n  app-1.0.0 -> b-2.0.0 requires a ^2\n  app-1.0.0 requires b *\n  app-1.0.0 -> a-2.0.0 requires b ^1");
+    }
+
+    #[test]
+    fn conflicting_roots() {
+        let r = resolver("conflicting_roots",&[("std",&std_index())]);
+        let err = r.resolve(&[dep("std","^2.0")]).unwrap_err().to_string();
+        assert_eq!(err,"no version of package std satisfies all requirements:\n  wy.toml requires std ^2.0");
+    }
+
+    #[test]
+    fn unindexed_cached() {
+        // Without an index, cached versions are used
+        let r = resolver("unindexed_cached",&[]);
+        for v in ["0.3.1","0.3.2","0.4.0"] {
+            let file = File::create(r.dir.join(format!("std-v{}.zip",v))).unwrap();
+            zip::ZipWriter::new(file).finish().unwrap();
+        }
+        assert_eq!(resolved(&r.resolve(&[dep("std","^0.3")]).unwrap()),vec!["std-0.3.2"]);
+    }
+
+    /// Write a package containing a given build configuration (if
+    /// any), returning its path.
+    fn package(name: &str, config: Option<&str>) -> PathBuf {
+        let path = util::test_dir(name).join("pkg-v1.0.0.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        if let Some(c) = config {
+            zip.start_file(CONFIG_FILE,zip::write::FileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut zip,c.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn read_package_dependencies() {
+        let zip = package("read_package_dependencies",Some("[package]\nname=\"pkg\"\n[dependencies]\nstd=\"^0.3\"\n"));
+        let deps = read_dependencies(&zip).unwrap();
+        assert_eq!(deps,vec![dep("std","^0.3")]);
+    }
+
+    #[test]
+    fn read_package_no_dependencies() {
+        let zip = package("read_package_no_dependencies",None);
+        assert_eq!(read_dependencies(&zip).unwrap(),vec![]);
+        let zip = package("read_package_no_dependencies",Some("[package]\nname=\"pkg\"\n"));
+        assert_eq!(read_dependencies(&zip).unwrap(),vec![]);
+    }
+
+    #[test]
+    fn read_package_invalid_dependencies() {
+        let zip = package("read_package_invalid_dependencies",Some("[dependencies]\nstd=\"^^\"\n"));
+        assert!(read_dependencies(&zip).is_err());
+        let zip = package("read_package_invalid_dependencies",Some("dependencies=1\n"));
+        assert!(read_dependencies(&zip).is_err());
+        let zip = package("read_package_invalid_dependencies",Some("[dependencies\n"));
+        assert!(read_dependencies(&zip).is_err());
+    }
+
+    #[test]
+    fn read_package_path_dependencies() {
+        let zip = package("read_package_path_dependencies",Some("[dependencies]\nstd={ path=\"../std\" }\n"));
+        let err = read_dependencies(&zip).unwrap_err().to_string();
+        assert!(err.ends_with("(unsupported path dependency std)"),"{}",err);
+    }
+
+    #[test]
+    fn missing_index() {
+        let r = resolver("missing_index",&[]);
+        let err = r.resolve(&[dep("std","*")]).unwrap_err().to_string();
+        assert_eq!(err,"no index for package std at https://registry.test/std/index.toml (and no cached version satisfies std *)");
+    }
+}
@@ -6,6 +6,7 @@ use crate::build;
 use crate::build::{PACKAGE_NAME,Artifact};
 use crate::platform;
 use crate::platform::{PluginError,whiley};
+use crate::jvm::JvmOutput;
 
 pub static VERIFY_DEFAULT : bool = true;
 pub static VERBOSE_DEFAULT : bool = false;
@@ -21,19 +22,23 @@ static BUILD_BOOGIE_TIMEOUT : Key = Key::new(&["build","boogie","timeout"]);
 static BUILD_BOOGIE_ARRAYTHEORY : Key = Key::new(&["build","boogie","useArrayTheory"]);
 static BUILD_BOOGIE_PROVERLOG : Key = Key::new(&["build","boogie","proverLog"]);
 static BUILD_BOOGIE_PROVERNAME : Key = Key::new(&["build","boogie","proverName"]);
+static BUILD_BOOGIE_VERSION : Key = Key::new(&["build","boogie","version"]);
 
 // ========================================================================
 // Platform
 // ========================================================================
 
 /// Identify the necessary dependencies (from Maven central) necessary
-/// to run the WhileyCompiler.
-static MAVEN_DEPS : &'static [&str] = &[
-    whiley::MAVEN_DEPS[0], // jmodelgen
-    whiley::MAVEN_DEPS[1], // wyc
+/// to run the WhileyCompiler.  The versions used can be overridden
+/// by `build.whiley.compiler` and `build.boogie.version`.
+pub static MAVEN_DEPS : &'static [&str] = &[
+    whiley::MAVEN_DEPS[0], // wyc
     "org.whiley:wyboogie:0.4.8",
 ];
 
+/// Minimum version of Java required to run the Boogie backend.
+static JAVA_VERSION : u32 = 8;
+
 pub struct BoogiePlatform {
     name: String,
     source: String,
@@ -45,7 +50,8 @@ pub struct BoogiePlatform {
     timeout: i64,
     array_theory: bool,
     prover_log: Option<String>,
-    prover_name: Option<String>
+    prover_name: Option<String>,
+    dependencies: Vec<String>
 }
 
 impl BoogiePlatform {
@@ -69,10 +75,13 @@ impl platform::JavaInstance for BoogiePlatform {
     fn name(&self) -> &'static str {
         "boogie"
     }
-    fn dependencies(&self) -> &'static [&'static str] {
-	MAVEN_DEPS
+    fn dependencies(&self) -> &[String] {
+	&self.dependencies
+    }
+    fn java_version(&self) -> u32 {
+	JAVA_VERSION
     }
-    fn arguments(&self) -> Vec<String> {
+    fn arguments(&self, _: &[String]) -> Vec<String> {
         let mut args = Vec::new();
         // Class to invoke
         args.push("wyboogie.Main".to_string());
@@ -114,7 +123,7 @@ impl platform::JavaInstance for BoogiePlatform {
     fn manifest(&self) -> Vec<build::Artifact> {
 	let mut artifacts = Vec::new();
 	// Register binary folder (if applicable)
-	if self.target != whiley::TARGET_DEFAULT {
+	if self.target != self.binary {
 	    artifacts.push(Artifact::BinaryFolder(PathBuf::from(&self.target)));
 	}
 	// Register the binary artifact
@@ -123,14 +132,19 @@ impl platform::JavaInstance for BoogiePlatform {
 	//
 	artifacts
     }
-    fn process(&self, output: &str) -> Result<Vec<build::Marker>,Box<dyn Error>> {
+    fn consumes(&self, line: &str) -> bool {
+	whiley::parse_line(Path::new(&self.source),line).is_some()
+    }
+    fn process(&self, output: &JvmOutput) -> Result<Vec<build::Marker>,Box<dyn Error>> {
+	platform::check_crash("wyboogie",output)?;
 	/// FIXME: this is broken!
 	let path = PathBuf::from(&self.source);
-	match whiley::parse_output(&path,output) {
-	    Some(markers) => Ok(markers),
-	    None => {
-	        Err(Box::new(PluginError{name:"wyboogie".to_string(),message: output.to_string()}))
-	    }
+	// Diagnostics may be reported on either stream
+	let markers = whiley::parse_output(&path,&output.to_string());
+	if !markers.is_empty() || output.success() {
+	    Ok(markers)
+	} else {
+	    Err(Box::new(PluginError{name:"wyboogie".to_string(),message: format!("failed ({})",output.status)}))
 	}
     }
 }
@@ -142,12 +156,12 @@ impl platform::JavaInstance for BoogiePlatform {
 pub struct Descriptor {}
 
 impl platform::Descriptor for Descriptor {
-    fn apply<'a>(&self, config: &'a Config, _: &Path) -> Result<platform::Instance,config::Error> {
+    fn apply<'a>(&self, config: &'a Config, _dir: &Path, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
 	// Extract configuration (if any)
         let name = config.get_string(&PACKAGE_NAME)?;
 	let source = config.get_string(&whiley::BUILD_WHILEY_SOURCE).unwrap_or(whiley::SOURCE_DEFAULT.to_string());
-	let binary = config.get_string(&whiley::BUILD_WHILEY_TARGET).unwrap_or(whiley::TARGET_DEFAULT.to_string());
-	let target = config.get_string(&BUILD_BOOGIE_TARGET).unwrap_or(whiley::TARGET_DEFAULT.to_string());
+	let binary = config.get_string(&whiley::BUILD_WHILEY_TARGET).unwrap_or(whiley::target_default(config));
+	let target = config.get_string(&BUILD_BOOGIE_TARGET).unwrap_or(whiley::target_default(config));
 	let verify = config.get_bool(&BUILD_BOOGIE_VERIFY).unwrap_or(VERIFY_DEFAULT);
 	let verbose = config.get_bool(&BUILD_BOOGIE_VERBOSE).unwrap_or(VERBOSE_DEFAULT);
 	let debug = config.get_bool(&BUILD_BOOGIE_DEBUG).unwrap_or(DEBUG_DEFAULT);
@@ -155,8 +169,9 @@ impl platform::Descriptor for Descriptor {
 	let array_theory = config.get_bool(&BUILD_BOOGIE_ARRAYTHEORY).unwrap_or(ARRAYTHEORY_DEFAULT);
 	let prover_log = config.get_string(&BUILD_BOOGIE_PROVERLOG).ok();
 	let prover_name = config.get_string(&BUILD_BOOGIE_PROVERNAME).ok();
+	let dependencies = vec![whiley::compiler(config,whileyhome)?,platform::coordinate(config,whileyhome,&BUILD_BOOGIE_VERSION,MAVEN_DEPS[1])?];
 	// Construct new instance on the heap
-	let instance = Box::new(BoogiePlatform{name,source,binary,target,verify,verbose,debug,timeout,array_theory,prover_log,prover_name});
+	let instance = Box::new(BoogiePlatform{name,source,binary,target,verify,verbose,debug,timeout,array_theory,prover_log,prover_name,dependencies});
 	// Return generic instance
 	Ok(platform::Instance::Java(instance))
     }
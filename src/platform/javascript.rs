@@ -1,37 +1,42 @@
 use std::error::Error;
 use std::path::{Path,PathBuf};
-use glob::glob;
+use glob::{glob,Pattern};
 use crate::config;
 use crate::config::{Config,Key};
 use crate::build;
 use crate::build::{PACKAGE_NAME,Artifact};
 use crate::platform;
 use crate::platform::{PluginError,whiley};
-use crate::jvm;
+use crate::jvm::JvmOutput;
 pub static STANDARD_DEFAULT : &'static str = "ES6";
 static BUILD_JAVASCRIPT_TARGET : Key = Key::new(&["build","js","target"]);
 static BUILD_JAVASCRIPT_STANDARD : Key = Key::new(&["build","js","standard"]);
 static BUILD_JAVASCRIPT_INCLUDES : Key = Key::new(&["build","js","includes"]);
+static BUILD_JAVASCRIPT_VERSION : Key = Key::new(&["build","js","version"]);
 
 // ========================================================================
 // Platform
 // ========================================================================
 
 /// Identify the necessary dependencies (from Maven central) necessary
-/// to run the WhileyCompiler.
-static MAVEN_DEPS : &'static [&str] = &[
-    whiley::MAVEN_DEPS[0], // jmodelgen
-    whiley::MAVEN_DEPS[1], // wyc
+/// to run the WhileyCompiler.  The versions used can be overridden
+/// by `build.whiley.compiler` and `build.js.version`.
+pub static MAVEN_DEPS : &'static [&str] = &[
+    whiley::MAVEN_DEPS[0], // wyc
     "org.whiley:wyjs:0.10.5",
 ];
 
+/// Minimum version of Java required to run the JavaScript backend.
+static JAVA_VERSION : u32 = 8;
+
 pub struct JavaScriptPlatform {
+    dir: PathBuf,
     name: String,
     source: String,
     target: String,
     standard: String,
     includes: Vec<String>,
-    whileypath: Vec<String>
+    dependencies: Vec<String>
 }
 
 impl JavaScriptPlatform {
@@ -44,11 +49,14 @@ impl JavaScriptPlatform {
     fn match_natives(&self) -> Vec<String> {
 	let mut matches = Vec::new();
 	//
+	let dir = Pattern::escape(self.dir.to_str().unwrap());
 	for i in &self.includes {
-	    for entry in glob(&i).expect("invalid pattern for key \"build.js.includes\"") {
+	    let pattern = Path::new(&dir).join(i);
+	    for entry in glob(pattern.to_str().unwrap()).expect("invalid pattern for key \"build.js.includes\"") {
 		match entry {
                     Ok(path) => {
-			matches.push(path.to_str().unwrap().to_string());
+			let f = path.strip_prefix(&self.dir).unwrap();
+			matches.push(f.to_str().unwrap().to_string());
                     }
                     Err(e) => println!("{:?}", e)
 		}
@@ -72,10 +80,13 @@ impl platform::JavaInstance for JavaScriptPlatform {
     fn name(&self) -> &'static str {
         "js"
     }
-    fn dependencies(&self) -> &'static [&'static str] {
-	MAVEN_DEPS
+    fn dependencies(&self) -> &[String] {
+	&self.dependencies
+    }
+    fn java_version(&self) -> u32 {
+	JAVA_VERSION
     }
-    fn arguments(&self) -> Vec<String> {
+    fn arguments(&self, whileypath: &[String]) -> Vec<String> {
         let mut args = Vec::new();
         // Class to invoke
         args.push("wyjs.Main".to_string());
@@ -96,16 +107,7 @@ impl platform::JavaInstance for JavaScriptPlatform {
 	args.push("-s".to_string());
 	args.push(self.standard.clone());
         // Whiley path
-        let mut whileypath = String::new();
-        if self.whileypath.len() > 0 {
-            whileypath.push_str("--whileypath=");
-            whileypath.push_str(self.whileypath.get(0).unwrap());
-            for e in &self.whileypath[1..] {
-                whileypath.push_str(jvm::classpath_sep());
-                whileypath.push_str(e);
-            }
-	    args.push(whileypath);
-        }
+        args.extend(super::whileypath_arg(whileypath));
         //
         args.append(&mut self.match_includes());
         //
@@ -114,7 +116,7 @@ impl platform::JavaInstance for JavaScriptPlatform {
     fn manifest(&self) -> Vec<build::Artifact> {
 	let mut artifacts = Vec::new();
 	// Register binary folder (if applicable)
-	if self.target != whiley::TARGET_DEFAULT {
+	if self.target != self.source {
 	    artifacts.push(Artifact::BinaryFolder(PathBuf::from(&self.target)));
 	}
 	// Register the binary artifact
@@ -127,10 +129,11 @@ impl platform::JavaInstance for JavaScriptPlatform {
 	//
 	artifacts
     }
-    fn process(&self, output: &str) -> Result<Vec<build::Marker>,Box<dyn Error>> {
-	if output.len() > 0 {
+    fn process(&self, output: &JvmOutput) -> Result<Vec<build::Marker>,Box<dyn Error>> {
+	platform::check_crash("wyjs",output)?;
+	if !output.success() {
 	    // The only way to get here should be through an internal failure.
-	    Err(Box::new(PluginError{name:"wyjs".to_string(),message: output.to_string()}))
+	    Err(Box::new(PluginError{name:"wyjs".to_string(),message: format!("failed ({})",output.status)}))
 	} else {
 	    Ok(Vec::new())
 	}
@@ -144,31 +147,16 @@ impl platform::JavaInstance for JavaScriptPlatform {
 pub struct Descriptor {}
 
 impl platform::Descriptor for Descriptor {
-    fn apply<'a>(&self, config: &'a Config, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
+    fn apply<'a>(&self, config: &'a Config, dir: &Path, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
 	// Extract configuration (if any)
         let name = config.get_string(&PACKAGE_NAME)?;
-	let source = config.get_string(&whiley::BUILD_WHILEY_TARGET).unwrap_or(whiley::TARGET_DEFAULT.to_string());
-	let target = config.get_string(&BUILD_JAVASCRIPT_TARGET).unwrap_or(whiley::TARGET_DEFAULT.to_string());
+	let source = config.get_string(&whiley::BUILD_WHILEY_TARGET).unwrap_or(whiley::target_default(config));
+	let target = config.get_string(&BUILD_JAVASCRIPT_TARGET).unwrap_or(whiley::target_default(config));
 	let standard = config.get_string(&BUILD_JAVASCRIPT_STANDARD).unwrap_or(STANDARD_DEFAULT.to_string());
 	let includes = config.get_string_array(&BUILD_JAVASCRIPT_INCLUDES).unwrap_or(Vec::new());
-        // Construct whileypath?
-        let mut whileypath = Vec::new();
-	// FIXME: this should be placed somewhere else, and use a
-	// resolved.
-        for s in config.find_keys(&whiley::DEPENDENCIES).unwrap_or(Vec::new()) {
-            let a = [&whiley::TMP,s.as_str()];
-            let k = Key::new(&a);
-	    let d = config.get_string(&k)?;
-	    let mut pb = PathBuf::new();
-	    pb.push(whileyhome);
-	    pb.push("repository");
-	    pb.push(format!("{}-v{}.zip",&s,&d));
-	    // FIXME: whileypath should be Vec of PathBuf
-	    let arg = pb.into_os_string().into_string().unwrap();
-	    whileypath.push(arg);
-        }
+	let dependencies = vec![whiley::compiler(config,whileyhome)?,platform::coordinate(config,whileyhome,&BUILD_JAVASCRIPT_VERSION,MAVEN_DEPS[1])?];
 	// Construct new instance on the heap
-	let instance = Box::new(JavaScriptPlatform{name,source,target,standard,includes,whileypath});
+	let instance = Box::new(JavaScriptPlatform{dir:dir.to_path_buf(),name,source,target,standard,includes,dependencies});
 	// Return generic instance
 	Ok(platform::Instance::Java(instance))
     }
@@ -8,7 +8,10 @@ use std::collections::HashMap;
 use std::path::Path;
 use crate::build;
 use crate::config;
-use crate::config::{Config};
+use crate::config::{Config,Key};
+use crate::jvm;
+use crate::jvm::JvmOutput;
+use crate::toolchain;
 
 // ============================================================
 // Instance
@@ -39,20 +42,32 @@ impl Instance {
 }
 
 /// Represents a platform implemented in Java.
-pub trait JavaInstance {
+pub trait JavaInstance : Sync {
     /// Get the name of this platform.
     fn name(&self) -> &'static str;
     /// Determine necessary Maven dependencies required for running
     /// this instance.
-    fn dependencies(&self) -> &[&str];
+    fn dependencies(&self) -> &[String];
+    /// Determine the minimum version of Java required for running
+    /// this instance.
+    fn java_version(&self) -> u32;
     /// Determine the command-line arguments which should be passed to
-    /// Java.  This includes identifying the main class.
-    fn arguments(&self) -> Vec<String>;
+    /// Java.  This includes identifying the main class.  The
+    /// whileypath identifies the resolved package dependencies.
+    fn arguments(&self, whileypath: &[String]) -> Vec<String>;
     /// Determine build artifacts relevant to this platform.
     fn manifest(&self) -> Vec<build::Artifact>;
+    /// Check whether a given line of output from the Java instance is
+    /// consumed when processing it (e.g. is a diagnostic reported as
+    /// a marker).  Other lines are forwarded as they are produced.
+    fn consumes(&self, _line: &str) -> bool {
+        false
+    }
     /// Process output from Java instance into a list of zero or more
-    /// markers.
-    fn process(&self,output:&str) -> Result<Vec<build::Marker>,Box<dyn error::Error>>;
+    /// markers.  Any other output will already have been forwarded,
+    /// so is not repeated.  A crash (e.g. from an uncaught exception)
+    /// is reported as an internal failure.
+    fn process(&self,output:&JvmOutput) -> Result<Vec<build::Marker>,Box<dyn error::Error>>;
 }
 
 /// Represents a platform implemented in Rust.
@@ -63,14 +78,49 @@ pub trait RustInstance {
     fn manifest(&self) -> Vec<build::Artifact>;
 }
 
+/// Determine the Maven coordinate of a tool used by a platform.  This
+/// is a given default coordinate (e.g. `org.whiley:wyc:0.10.18`), as
+/// adjusted by the default toolchain (if any), unless a version is
+/// given for a given key (e.g. `build.whiley.compiler = "0.10.17"`)
+/// which then overrides it.
+pub fn coordinate(config: &Config, whileyhome: &Path, key: &Key, default: &str) -> Result<String,config::Error> {
+    match config.get_string(key) {
+        Ok(v) => versioned(default,&v).ok_or(config::Error::Invalid(key.to_string())),
+        Err(config::Error::Invalid(_)) => toolchain::tool(whileyhome,default),
+        Err(e) => Err(e)
+    }
+}
+
+/// Construct the argument passing a given whileypath to a Whiley
+/// tool (e.g. `--whileypath=a.zip:b.zip`), or `None` if it is empty.
+pub fn whileypath_arg(whileypath: &[String]) -> Option<String> {
+    if whileypath.is_empty() {
+        None
+    } else {
+        Some(format!("--whileypath={}",whileypath.join(jvm::classpath_sep())))
+    }
+}
+
+/// Replace the version of a given Maven coordinate (e.g. replacing
+/// `0.10.18` in `org.whiley:wyc:0.10.18`).  This fails if the version
+/// is empty or is itself a coordinate.
+pub fn versioned(coordinate: &str, version: &str) -> Option<String> {
+    if version.is_empty() || version.contains(':') {
+        None
+    } else {
+        let (artifact,_) = coordinate.rsplit_once(':').unwrap();
+        Some(format!("{}:{}",artifact,version))
+    }
+}
+
 // ============================================================
 // Error
 // ============================================================
 
 #[derive(Clone)]
 pub struct PluginError {
-    name: String,
-    message: String
+    pub name: String,
+    pub message: String
 }
 
 impl fmt::Display for PluginError {
@@ -87,6 +137,41 @@ impl fmt::Debug for PluginError {
 
 impl error::Error for PluginError {}
 
+/// Indicates a platform crashed (e.g. from an uncaught exception),
+/// rather than reporting errors in the program being built.
+#[derive(Clone)]
+pub struct InternalFailure {
+    name: String,
+    reason: String
+}
+
+impl fmt::Display for InternalFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: internal failure ({})",self.name,self.reason)
+    }
+}
+
+impl fmt::Debug for InternalFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: internal failure ({})",self.name,self.reason)
+    }
+}
+
+impl error::Error for InternalFailure {}
+
+/// Check whether a given tool crashed, reporting this as an internal
+/// failure.  Only the first line of the stack trace (or other details)
+/// is reported, since the remainder will already have been forwarded.
+pub fn check_crash(name: &str, output: &JvmOutput) -> Result<(),InternalFailure> {
+    match output.crash() {
+        Some(trace) => {
+            let reason = trace.lines().next().unwrap_or_default().to_string();
+            Err(InternalFailure{name:name.to_string(),reason})
+        }
+        None => Ok(())
+    }
+}
+
 // ============================================================
 // Descriptor
 // ============================================================
@@ -94,8 +179,10 @@ impl error::Error for PluginError {}
 /// A mechanism for programmatically constructing a platform.
 pub trait Descriptor {
     /// Apply this descriptor to a given TOML configuration, thereby
-    /// allowing customisation of the platform instantiation.
-    fn apply<'a>(&self, config: &'a Config, whileypath: &'a Path)->Result<Instance,config::Error>;
+    /// allowing customisation of the platform instantiation.  The
+    /// package directory is that against which any (relative) paths
+    /// are resolved.
+    fn apply<'a>(&self, config: &'a Config, dir: &Path, whileypath: &'a Path)->Result<Instance,config::Error>;
 }
 
 // ============================================================
@@ -7,7 +7,7 @@ use crate::build;
 use crate::build::{PACKAGE_NAME,Artifact};
 use crate::platform;
 use crate::platform::{PluginError,whiley};
-use crate::jvm;
+use crate::jvm::JvmOutput;
 
 static BUILD_CHECK_MIN : Key = Key::new(&["build","check","min"]);
 static BUILD_CHECK_MAX : Key = Key::new(&["build","check","max"]);
@@ -29,25 +29,18 @@ static TIMEOUT_DEFAULT : i64 = 1_000_000;
 // Platform
 // ========================================================================
 
-/// Identify the necessary dependencies (from Maven central) necessary
-/// to run the WhileyCompiler.
-static MAVEN_DEPS : &'static [&str] = &[
-    whiley::MAVEN_DEPS[0], // jmodelgen
-    whiley::MAVEN_DEPS[1], // wyc
-];
-
 pub struct QuickCheckPlatform {
     name: String,
     source: PathBuf,
     target: String,
-    whileypath: Vec<String>,
     min: i64,
     max: i64,
     length: i64,
     depth: i64,
     width: i64,
     rotation: i64,
-    timeout: i64
+    timeout: i64,
+    dependencies: Vec<String>
 }
 
 impl QuickCheckPlatform {
@@ -62,10 +55,13 @@ impl platform::JavaInstance for QuickCheckPlatform {
     fn name(&self) -> &'static str {
         "check"
     }
-    fn dependencies(&self) -> &'static [&'static str] {
-	MAVEN_DEPS
+    fn dependencies(&self) -> &[String] {
+	&self.dependencies
+    }
+    fn java_version(&self) -> u32 {
+	whiley::JAVA_VERSION
     }
-    fn arguments(&self) -> Vec<String> {
+    fn arguments(&self, whileypath: &[String]) -> Vec<String> {
         let mut args = Vec::new();
         // Class to invoke
         args.push("wyc.Check".to_string());
@@ -75,16 +71,7 @@ impl platform::JavaInstance for QuickCheckPlatform {
         target.push_str(self.target.as_str());
         args.push(target);
         // Whiley path
-        let mut whileypath = String::new();
-        if self.whileypath.len() > 0 {
-            whileypath.push_str("--whileypath=");
-            whileypath.push_str(self.whileypath.get(0).unwrap());
-            for e in &self.whileypath[1..] {
-                whileypath.push_str(jvm::classpath_sep());
-                whileypath.push_str(e);
-            }
-	    args.push(whileypath);
-        }
+        args.extend(super::whileypath_arg(whileypath));
 	// Context Configuration
 	args.push(format!("--min={}",self.min));
 	args.push(format!("--max={}",self.max));
@@ -102,12 +89,17 @@ impl platform::JavaInstance for QuickCheckPlatform {
 	// This platform generates no files
 	Vec::new()
     }
-    fn process(&self, output: &str) -> Result<Vec<build::Marker>,Box<dyn Error>> {
-	match whiley::parse_output(&self.source,output) {
-	    Some(markers) => Ok(markers),
-	    None => {
-		Err(Box::new(PluginError{name:"wyqc".to_string(),message: output.to_string()}))
-	    }
+    fn consumes(&self, line: &str) -> bool {
+	whiley::parse_line(&self.source,line).is_some()
+    }
+    fn process(&self, output: &JvmOutput) -> Result<Vec<build::Marker>,Box<dyn Error>> {
+	platform::check_crash("wyqc",output)?;
+	// Diagnostics may be reported on either stream
+	let markers = whiley::parse_output(&self.source,&output.to_string());
+	if !markers.is_empty() || output.success() {
+	    Ok(markers)
+	} else {
+	    Err(Box::new(PluginError{name:"wyqc".to_string(),message: format!("failed ({})",output.status)}))
 	}
     }
 }
@@ -119,11 +111,11 @@ impl platform::JavaInstance for QuickCheckPlatform {
 pub struct Descriptor {}
 
 impl platform::Descriptor for Descriptor {
-    fn apply<'a>(&self, config: &'a Config, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
+    fn apply<'a>(&self, config: &'a Config, _dir: &Path, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
 	// Extract configuration (if any)
         let name = config.get_string(&PACKAGE_NAME)?;
 	let source = config.get_path(&whiley::BUILD_WHILEY_SOURCE).unwrap_or(PathBuf::from(whiley::SOURCE_DEFAULT));
-	let target = config.get_string(&whiley::BUILD_WHILEY_TARGET).unwrap_or(whiley::TARGET_DEFAULT.to_string());
+	let target = config.get_string(&whiley::BUILD_WHILEY_TARGET).unwrap_or(whiley::target_default(config));
 	let min = config.get_int(&BUILD_CHECK_MIN).unwrap_or(MIN_DEFAULT);
 	let max = config.get_int(&BUILD_CHECK_MAX).unwrap_or(MAX_DEFAULT);
 	let length = config.get_int(&BUILD_CHECK_LENGTH).unwrap_or(LENGTH_DEFAULT);
@@ -131,24 +123,10 @@ impl platform::Descriptor for Descriptor {
 	let width = config.get_int(&BUILD_CHECK_WIDTH).unwrap_or(ROTATION_DEFAULT);
 	let rotation = config.get_int(&BUILD_CHECK_ROTATION).unwrap_or(ROTATION_DEFAULT);
 	let timeout = config.get_int(&BUILD_CHECK_TIMEOUT).unwrap_or(TIMEOUT_DEFAULT);
-        // Construct whileypath?
-        let mut whileypath = Vec::new();
-	// FIXME: this should be placed somewhere else, and use a
-	// resolved.
-        for s in config.find_keys(&whiley::DEPENDENCIES).unwrap_or(Vec::new()) {
-            let a = [&whiley::TMP,s.as_str()];
-            let k = Key::new(&a);
-	    let d = config.get_string(&k)?;
-	    let mut pb = PathBuf::new();
-	    pb.push(whileyhome);
-	    pb.push("repository");
-	    pb.push(format!("{}-v{}.zip",&s,&d));
-	    // FIXME: whileypath should be Vec of PathBuf
-	    let arg = pb.into_os_string().into_string().unwrap();
-	    whileypath.push(arg);
-        }
+	// QuickCheck is part of the WhileyCompiler
+	let dependencies = vec![whiley::compiler(config,whileyhome)?];
 	// Construct new instance on the heap
-	let instance = Box::new(QuickCheckPlatform{name,source,target,whileypath,min,max,length,depth,width,rotation,timeout});
+	let instance = Box::new(QuickCheckPlatform{name,source,target,min,max,length,depth,width,rotation,timeout,dependencies});
 	// Return generic instance
 	Ok(platform::Instance::Java(instance))
     }
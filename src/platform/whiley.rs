@@ -1,11 +1,12 @@
 use std::error::Error;
 use std::path::{Path,PathBuf};
-use glob::glob;
+use glob::{glob,Pattern};
+use log::info;
 use crate::config;
 use crate::config::{Config,Key};
 use crate::build;
 use crate::build::{PACKAGE_NAME,Artifact};
-use crate::jvm;
+use crate::jvm::JvmOutput;
 use crate::platform;
 use crate::platform::{PluginError};
 
@@ -20,31 +21,51 @@ pub static INCLUDES_DEFAULT : &'static str = "**/*.whiley";
 /// Default main method to execute
 pub static MAIN_DEFAULT : &'static str = "main::main";
 
-pub static DEPENDENCIES : Key = Key::new(&["dependencies"]);
 pub static BUILD_WHILEY_SOURCE : Key = Key::new(&["build","whiley","source"]);
 pub static BUILD_WHILEY_TARGET : Key = Key::new(&["build","whiley","target"]);
 pub static BUILD_WHILEY_INCLUDES : Key = Key::new(&["build","whiley","includes"]);
 pub static BUILD_WHILEY_LIBRARY : Key = Key::new(&["build","whiley","library"]);
 pub static BUILD_WHILEY_MAIN : Key = Key::new(&["build","whiley","main"]);
+pub static BUILD_WHILEY_COMPILER : Key = Key::new(&["build","whiley","compiler"]);
+
+/// Determine the default binary folder for all platforms.  This is
+/// given by `build.target` (if set), thus allowing several packages
+/// to share one binary folder (e.g. within a workspace).
+pub fn target_default(config: &Config) -> String {
+    config.get_string(&build::BUILD_TARGET).unwrap_or(TARGET_DEFAULT.to_string())
+}
 
 // ========================================================================
 // Platform
 // ========================================================================
 
 /// Identify the necessary dependencies (from Maven central) necessary
-/// to run the WhileyCompiler.
+/// to run the WhileyCompiler.  Any dependencies of these
+/// (e.g. `jmodelgen`) are determined from their POM files.  The
+/// version of the compiler used can be overridden (see `compiler()`).
 pub static MAVEN_DEPS : &'static [&str] = &[
-    "org.whiley:jmodelgen:0.4.3",
     "org.whiley:wyc:0.10.18",
 ];
 
+/// Minimum version of Java required to run the WhileyCompiler.
+pub static JAVA_VERSION : u32 = 8;
+
+/// Determine the Maven coordinate of the WhileyCompiler to use.  This
+/// is given by `build.whiley.compiler` (if set), thus allowing
+/// different packages to use different compiler versions.  Otherwise,
+/// it is given by the default toolchain (if any).
+pub fn compiler(config: &Config, whileyhome: &Path) -> Result<String,config::Error> {
+    platform::coordinate(config,whileyhome,&BUILD_WHILEY_COMPILER,MAVEN_DEPS[0])
+}
+
 pub struct WhileyPlatform {
+    dir: PathBuf,
     name: String,
     linking: bool,
     source: PathBuf,
     target: PathBuf,
     includes: String,
-    whileypath: Vec<String>
+    dependencies: Vec<String>
 }
 
 impl WhileyPlatform {
@@ -52,17 +73,15 @@ impl WhileyPlatform {
     fn match_includes(&self) -> Vec<String> {
         // TODO: this is all rather ugly if you ask me.
 	let mut matches = Vec::new();
-        let mut includes = PathBuf::new();
-	includes.push(&self.source);
+        let source = self.dir.join(&self.source);
+        let mut includes = PathBuf::from(Pattern::escape(source.to_str().unwrap()));
         includes.push(self.includes.as_str());
 	let mut sincludes = includes.to_str().unwrap();
         //
         for entry in glob(&sincludes).expect("invalid pattern for key \"build.whiley.includes\"") {
             match entry {
                 Ok(path) => {
-                    //let f = path.into_os_string().into_string().unwrap();
-                    //let n = self.source.len()+1;
-		    let f = path.strip_prefix(&self.source).unwrap();
+		    let f = path.strip_prefix(&source).unwrap();
                     matches.push(f.to_str().unwrap().to_string());
                 }
                 Err(e) => println!("{:?}", e)
@@ -85,10 +104,13 @@ impl platform::JavaInstance for WhileyPlatform {
     fn name(&self) -> &'static str {
         "whiley"
     }
-    fn dependencies(&self) -> &'static [&'static str] {
-	MAVEN_DEPS
+    fn dependencies(&self) -> &[String] {
+	&self.dependencies
+    }
+    fn java_version(&self) -> u32 {
+	JAVA_VERSION
     }
-    fn arguments(&self) -> Vec<String> {
+    fn arguments(&self, whileypath: &[String]) -> Vec<String> {
         let mut args = Vec::new();
         // Class to invoke
         args.push("wyc.Compiler".to_string());
@@ -111,16 +133,7 @@ impl platform::JavaInstance for WhileyPlatform {
         target.push_str(self.target.to_str().unwrap());
         args.push(target);
         // Whiley path
-        let mut whileypath = String::new();
-        if self.whileypath.len() > 0 {
-            whileypath.push_str("--whileypath=");
-            whileypath.push_str(self.whileypath.get(0).unwrap());
-            for e in &self.whileypath[1..] {
-                whileypath.push_str(jvm::classpath_sep());
-                whileypath.push_str(e);
-            }
-	    args.push(whileypath);
-        }
+        args.extend(super::whileypath_arg(whileypath));
         //
         args.append(&mut self.match_includes());
         //
@@ -141,40 +154,44 @@ impl platform::JavaInstance for WhileyPlatform {
 	//
 	artifacts
     }
-    fn process(&self, output: &str) -> Result<Vec<build::Marker>,Box<dyn Error>> {
-	match parse_output(&self.source,output) {
-	    Some(markers) => Ok(markers),
-	    None => {
-		Err(Box::new(PluginError{name:"wyc".to_string(),message: output.to_string()}))
-	    }
+    fn consumes(&self, line: &str) -> bool {
+	parse_line(&self.source,line).is_some()
+    }
+    fn process(&self, output: &JvmOutput) -> Result<Vec<build::Marker>,Box<dyn Error>> {
+	platform::check_crash("wyc",output)?;
+	// Diagnostics may be reported on either stream
+	let markers = parse_output(&self.source,&output.to_string());
+	if !markers.is_empty() || output.success() {
+	    Ok(markers)
+	} else {
+	    Err(Box::new(PluginError{name:"wyc".to_string(),message: format!("failed ({})",output.status)}))
 	}
     }
 }
 
-pub fn parse_output(source: &PathBuf, output: &str) -> Option<Vec<build::Marker>> {
-    let mut markers = Vec::new();
-    // Process each line of output
-    for line in output.lines() {
-	// Split line into components
-	let split : Vec<&str> = line.split("|").collect();
-	if split.len() != 5 {
-	    return None;
-	}
-	// Parse components
-	let kind = build::Kind::SyntaxError;
-	let mut path = source.clone();
-	path.push(split[0]);
-	let start = split[1].parse();
-	let end = split[2].parse();
-	if start.is_err() || end.is_err() {
-	    return None;
-	}
-	let message = split[4].to_string();
-	// Done
-	markers.push(build::Marker::new(kind,path,start.unwrap(),end.unwrap(),message));
+/// Extract the markers from the output of a Whiley tool.  Any other
+/// lines (e.g. progress) are ignored, since these will already have
+/// been forwarded.
+pub fn parse_output(source: &Path, output: &str) -> Vec<build::Marker> {
+    output.lines().filter_map(|l| parse_line(source,l)).collect()
+}
+
+/// Parse a line of output from a Whiley tool into a marker, provided
+/// it has the form `file|start|end|kind|message`.
+pub fn parse_line(source: &Path, line: &str) -> Option<build::Marker> {
+    // Split line into components
+    let split : Vec<&str> = line.split('|').collect();
+    if split.len() != 5 {
+	return None;
     }
+    // Parse components
+    let kind = build::Kind::SyntaxError;
+    let path = source.join(split[0]);
+    let start = split[1].parse().ok()?;
+    let end = split[2].parse().ok()?;
+    let message = split[4].to_string();
     // Done
-    Some(markers)
+    Some(build::Marker::new(kind,path,start,end,message))
 }
 
 // ========================================================================
@@ -183,34 +200,18 @@ pub fn parse_output(source: &PathBuf, output: &str) -> Option<Vec<build::Marker>
 
 pub struct Descriptor {}
 
-pub const TMP : &'static str = "dependencies";
-
 impl platform::Descriptor for Descriptor {
-    fn apply<'a>(&self, config: &'a Config, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
+    fn apply<'a>(&self, config: &'a Config, dir: &Path, whileyhome: &Path) -> Result<platform::Instance,config::Error> {
 	// Extract configuration (if any)
         let name = config.get_string(&PACKAGE_NAME)?;
 	let linking = !config.get_bool(&BUILD_WHILEY_LIBRARY).unwrap_or(LIBRARY_DEFAULT);
 	let source = config.get_path(&BUILD_WHILEY_SOURCE).unwrap_or(PathBuf::from(SOURCE_DEFAULT));
-	let target = config.get_path(&BUILD_WHILEY_TARGET).unwrap_or(PathBuf::from(TARGET_DEFAULT));
+	let target = config.get_path(&BUILD_WHILEY_TARGET).unwrap_or(PathBuf::from(target_default(config)));
 	let includes = config.get_string(&BUILD_WHILEY_INCLUDES).unwrap_or(INCLUDES_DEFAULT.to_string());
-        // Construct whileypath?
-        let mut whileypath = Vec::new();
-	// FIXME: this should be placed somewhere else, and use a
-	// resolved.
-        for s in config.find_keys(&DEPENDENCIES).unwrap_or(Vec::new()) {
-            let a = [&TMP,s.as_str()];
-            let k = Key::new(&a);
-	    let d = config.get_string(&k)?;
-	    let mut pb = PathBuf::new();
-	    pb.push(whileyhome);
-	    pb.push("repository");
-	    pb.push(format!("{}-v{}.zip",&s,&d));
-	    // FIXME: whileypath should be Vec of PathBuf
-	    let arg = pb.into_os_string().into_string().unwrap();
-	    whileypath.push(arg);
-        }
+	let dependencies = vec![compiler(config,whileyhome)?];
+	info!("Using compiler {}",dependencies[0]);
 	// Construct new instance on the heap
-	let instance = Box::new(WhileyPlatform{name,linking,source,target,includes,whileypath});
+	let instance = Box::new(WhileyPlatform{dir:dir.to_path_buf(),name,linking,source,target,includes,dependencies});
 	// Return generic instance
 	Ok(platform::Instance::Java(instance))
     }
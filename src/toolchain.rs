@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path,PathBuf};
+use toml;
+use toml::Value;
+use toml::value::Table;
+use crate::config;
+use crate::config::{Config,Key};
+
+/// Name of the directory within WHILEYHOME holding installed
+/// toolchains.
+pub const TOOLCHAINS_DIR : &str = "toolchains";
+
+/// Name of the file (within the toolchains directory) which names the
+/// default toolchain.
+const DEFAULT_FILE : &str = "default";
+
+/// Name of the file (within a toolchain's directory) which describes
+/// it.
+const TOOLCHAIN_FILE : &str = "toolchain.toml";
+
+const HEADER : &str = "# This file is automatically generated by wy.\n# It records the tools making up this toolchain.\n\n";
+
+pub static TOOLCHAIN_TOOLS : Key = Key::new(&["toolchain","tools"]);
+
+// ===================================================================
+// Toolchain
+// ===================================================================
+
+/// A set of Whiley tool jars (e.g. `wyc`, `wyjs` and `wyboogie`,
+/// along with their dependencies) installed within WHILEYHOME under a
+/// given name (i.e. the compiler version).  The jars themselves are
+/// held in a `maven` subdirectory, which is searched before the Maven
+/// cache.  Thus, an installed toolchain is available offline and is
+/// unaffected by cleaning the cache.
+pub struct Toolchain {
+    /// Name of this toolchain (e.g. `0.10.18`)
+    pub name: String,
+    /// Maven coordinates of the tools making up this toolchain
+    pub tools: Vec<String>
+}
+
+impl Toolchain {
+    /// Get the directory of the toolchain with a given name.
+    pub fn dir(whileyhome: &Path, name: &str) -> PathBuf {
+        whileyhome.join(TOOLCHAINS_DIR).join(name)
+    }
+
+    /// Get the directory in which the toolchain with a given name is
+    /// assembled before being moved into place.  This is hidden so
+    /// that it is never mistaken for an installed toolchain.
+    pub fn staging_dir(whileyhome: &Path, name: &str) -> PathBuf {
+        whileyhome.join(TOOLCHAINS_DIR).join(format!(".{}.staging",name))
+    }
+
+    /// Read the installed toolchain with a given name.
+    pub fn read(whileyhome: &Path, name: &str) -> Result<Toolchain,config::Error> {
+        let path = Toolchain::dir(whileyhome,name).join(TOOLCHAIN_FILE);
+        let contents = fs::read_to_string(&path).map_err(|_| config::Error::Invalid(path.display().to_string()))?;
+        let config = Config::from_str(&contents)?;
+        let tools = config.get_string_array(&TOOLCHAIN_TOOLS)?;
+        Ok(Toolchain{name:name.to_string(),tools})
+    }
+
+    /// Write the description of this toolchain into a given
+    /// directory.
+    pub fn write(&self, dir: &Path) -> Result<(),Box<dyn Error>> {
+        let tools = self.tools.iter().map(|t| Value::String(t.clone())).collect();
+        let mut toolchain = Table::new();
+        toolchain.insert("tools".to_string(),Value::Array(tools));
+        let mut root = Table::new();
+        root.insert("toolchain".to_string(),Value::Table(toolchain));
+        fs::write(dir.join(TOOLCHAIN_FILE),format!("{}{}",HEADER,toml::to_string(&Value::Table(root))?))?;
+        Ok(())
+    }
+
+    /// Determine the coordinate of a given tool in this toolchain.
+    /// This is the coordinate in this toolchain with the same group
+    /// and artifact as a given default coordinate (e.g.
+    /// `org.whiley:wyc:0.10.18`) or, if there is none, the default.
+    pub fn tool(&self, default: &str) -> String {
+        let (artifact,_) = default.rsplit_once(':').unwrap();
+        for t in &self.tools {
+            if t.rsplit_once(':').is_some_and(|(a,_)| a == artifact) {
+                return t.clone();
+            }
+        }
+        default.to_string()
+    }
+}
+
+/// Determine all toolchains installed within WHILEYHOME, ordered by
+/// name.
+pub fn installed(whileyhome: &Path) -> Result<Vec<Toolchain>,Box<dyn Error>> {
+    let mut toolchains = Vec::new();
+    let entries = match fs::read_dir(whileyhome.join(TOOLCHAINS_DIR)) {
+        Ok(es) => es,
+        Err(_) => { return Ok(toolchains); }
+    };
+    for e in entries {
+        let path = e?.path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        // Skip any (partially) staged toolchains
+        if !name.starts_with('.') && path.join(TOOLCHAIN_FILE).exists() {
+            toolchains.push(Toolchain::read(whileyhome,&name)?);
+        }
+    }
+    toolchains.sort_by(|a,b| a.name.cmp(&b.name));
+    Ok(toolchains)
+}
+
+/// Determine the Maven caches of all toolchains installed within
+/// WHILEYHOME.
+pub fn layers(whileyhome: &Path) -> Result<Vec<PathBuf>,Box<dyn Error>> {
+    Ok(installed(whileyhome)?.iter().map(|t| Toolchain::dir(whileyhome,&t.name).join("maven")).collect())
+}
+
+/// Determine the name of the default toolchain (if any).
+pub fn default_name(whileyhome: &Path) -> Option<String> {
+    let path = whileyhome.join(TOOLCHAINS_DIR).join(DEFAULT_FILE);
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// Determine the default toolchain (if any).
+pub fn default_toolchain(whileyhome: &Path) -> Result<Option<Toolchain>,config::Error> {
+    match default_name(whileyhome) {
+        Some(name) => Ok(Some(Toolchain::read(whileyhome,&name)?)),
+        None => Ok(None)
+    }
+}
+
+/// Set (or, if none is given, clear) the default toolchain.
+pub fn set_default(whileyhome: &Path, name: Option<&str>) -> io::Result<()> {
+    let path = whileyhome.join(TOOLCHAINS_DIR).join(DEFAULT_FILE);
+    match name {
+        Some(n) => fs::write(path,format!("{}\n",n)),
+        None if path.exists() => fs::remove_file(path),
+        None => Ok(())
+    }
+}
+
+/// Determine the coordinate of a given tool to use by default.  This
+/// is given by the default toolchain (if any), otherwise it is the
+/// given (built-in) coordinate.
+pub fn tool(whileyhome: &Path, default: &str) -> Result<String,config::Error> {
+    match default_toolchain(whileyhome)? {
+        Some(t) => Ok(t.tool(default)),
+        None => Ok(default.to_string())
+    }
+}
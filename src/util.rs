@@ -1,4 +1,11 @@
+use std::fs;
+use std::fs::{File,TryLockError};
+use std::io;
+use std::path::{Path,PathBuf};
 use std::str::Chars;
+use log::warn;
+use sha1::Sha1;
+use sha2::{Digest,Sha256};
 
 // ===================================================================
 // Line Abstraction
@@ -101,3 +108,100 @@ impl<'a> Iterator for LineIter<'a> {
 pub fn line_offsets<'a>(contents: &'a str) -> LineIter<'a> {
     LineIter::new(contents)
 }
+
+// ===================================================================
+// Checksums
+// ===================================================================
+
+/// Compute the SHA-256 digest of a given file, returning it as a
+/// lowercase hex string.
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Compute the SHA-256 digest of a given sequence of bytes, returning
+/// it as a lowercase hex string.
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// Compute the SHA-1 digest of a given sequence of bytes, returning
+/// it as a lowercase hex string.
+pub fn sha1_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha1::digest(bytes))
+}
+
+/// Convert a sequence of bytes into a lowercase hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}",b)).collect()
+}
+
+// ===================================================================
+// Directories
+// ===================================================================
+
+/// Determine all files within a given directory, including those
+/// within its subdirectories.  A directory which does not exist has
+/// no files.
+pub fn files_within(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(es) => es,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => { return Ok(files); }
+        Err(e) => { return Err(e); }
+    };
+    for e in entries {
+        let path = e?.path();
+        if path.is_dir() {
+            files.extend(files_within(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Create a fresh (i.e. empty) temporary directory for a given test.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wy-test-{}",name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// ===================================================================
+// File Locking
+// ===================================================================
+
+/// Name of the lock file used to guard a cache directory.
+const LOCK_FILE : &str = ".lock";
+
+/// An advisory lock on a cache directory (e.g. the Maven cache in
+/// WHILEYHOME) held by this process.  This prevents concurrent
+/// invocations from updating the same cache at the same time.  The
+/// lock is released when dropped.
+pub struct FileLock {
+    _file: File
+}
+
+impl FileLock {
+    /// Acquire the lock on a given directory, blocking until any
+    /// other process holding it has released it.
+    pub fn acquire(dir: &Path) -> io::Result<FileLock> {
+        fs::create_dir_all(dir)?;
+        let file = File::create(dir.join(LOCK_FILE))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                warn!("Blocking: waiting for file lock on {}",dir.display());
+                file.lock()?;
+            }
+            Err(TryLockError::Error(e)) => { return Err(e); }
+        }
+        Ok(FileLock{_file:file})
+    }
+}
@@ -0,0 +1,227 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::{Component,Path,PathBuf};
+use log::info;
+use reqwest::Url;
+use crate::build;
+use crate::build::{Build,BUILD_TARGET,PACKAGE_CENTRAL,REGISTRY_URL};
+use crate::config;
+use crate::config::{Config,Key};
+use crate::index;
+use crate::lock::{Lock,LOCK_FILE};
+use crate::package::Dependency;
+use crate::platform;
+use crate::platform::whiley::TARGET_DEFAULT;
+
+pub static WORKSPACE_MEMBERS : Key = Key::new(&["workspace","members"]);
+
+// ===================================================================
+// Member
+// ===================================================================
+
+/// A package within a workspace.
+pub struct Member {
+    /// Directory containing the member's build configuration.
+    pub dir: PathBuf,
+    /// The member's build configuration.  This includes any settings
+    /// inherited from the workspace (e.g. its binary folder).
+    pub config: Config,
+    pub build: Build
+}
+
+// ===================================================================
+// Workspace
+// ===================================================================
+
+/// A collection of packages built together from one root `wy.toml`.
+/// For example:
+///
+/// ```toml
+/// [workspace]
+/// members = ["std", "app"]
+/// ```
+///
+/// Members are built in dependency order (i.e. after any members on
+/// which they depend via path dependencies), and share a single lock
+/// file and binary folder located in the workspace root.
+pub struct Workspace {
+    /// Members of this workspace in dependency order.
+    pub members: Vec<Member>,
+    /// Registry from which the members' dependencies are resolved.
+    pub registry: Url
+}
+
+impl Workspace {
+    /// Construct the workspace described by a given configuration
+    /// (which must be in the current directory).  This returns `None`
+    /// if the configuration does not describe a workspace.
+    pub fn from_config<'a>(config: &Config, whileyhome: &Path, registry: &'a platform::Registry<'a>) -> Result<Option<Workspace>,Box<dyn Error>> {
+        let names = match config.get_string_array(&WORKSPACE_MEMBERS) {
+            Ok(ns) => ns,
+            Err(config::Error::Invalid(_)) => { return Ok(None); }
+            Err(e) => { return Err(Box::new(e)); }
+        };
+        let url = config.get_string(&REGISTRY_URL).unwrap_or(PACKAGE_CENTRAL.to_string());
+        let url = index::parse_root(&url).ok_or_else(|| config::Error::Invalid(REGISTRY_URL.to_string()))?;
+        let root = env::current_dir()?;
+        //
+        let mut members = Vec::new();
+        for n in names {
+            let path : PathBuf = n.split('/').collect();
+            // Members must live within the workspace root, since
+            // their binary folder is given relative to it.
+            if !path.components().all(|c| matches!(c,Component::Normal(_) | Component::CurDir)) {
+                let message = format!("member \"{}\" is not within workspace",n);
+                return Err(Box::new(WorkspaceError{message}));
+            }
+            let dir = root.join(&path).canonicalize()?;
+            let config_file = read_to_string(dir.join("wy.toml"))?;
+            let mut config = Config::from_str(config_file.as_str())?;
+            // Inherit settings from workspace
+            if config.get_string(&BUILD_TARGET).is_err() {
+                let depth = path.components().filter(|c| matches!(c,Component::Normal(_))).count();
+                let target = format!("{}{}","../".repeat(depth),TARGET_DEFAULT);
+                config.set_string(&BUILD_TARGET,&target)?;
+            }
+            if config.get_string(&REGISTRY_URL).is_err() {
+                config.set_string(&REGISTRY_URL,url.as_str())?;
+            }
+            let build = Build::from_str(&config,&dir,whileyhome,registry)?;
+            if members.iter().any(|m: &Member| m.build.name == build.name) {
+                let message = format!("duplicate member \"{}\"",build.name);
+                return Err(Box::new(WorkspaceError{message}));
+            }
+            members.push(Member{dir,config,build});
+        }
+        let members = order(members)?;
+        Ok(Some(Workspace{members,registry:url}))
+    }
+
+    /// Get the member with a given package name (if it exists).
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        self.members.iter().find(|m| m.build.name == name)
+    }
+
+    /// Determine the (registry) dependencies of all members.
+    pub fn dependencies(&self) -> Vec<Dependency> {
+        let mut deps : Vec<Dependency> = Vec::new();
+        for m in &self.members {
+            for d in &m.build.dependencies {
+                if !deps.contains(d) {
+                    deps.push(d.clone());
+                }
+            }
+        }
+        deps
+    }
+
+    /// Build all members of this workspace in dependency order.  The
+    /// dependencies of all members are resolved together, such that
+    /// they agree on the version of every package used.  This returns
+    /// the whileypath for each member, or `None` if a build failed.
+    pub fn build(&self, whileyhome: &Path) -> Result<Option<Vec<Vec<String>>>,Box<dyn Error>> {
+        // Read lock file (if it exists)
+        let path = Path::new(LOCK_FILE);
+        let mut lock = Lock::load(path)?;
+        let deps = self.dependencies();
+        let layers : Vec<PathBuf> = self.members.iter().flat_map(|m| m.build.layers.iter().cloned()).collect();
+        build::resolve_packages(&deps,&self.registry,&layers,whileyhome,&mut lock)?;
+        // Build each member in turn
+        let mut whileypaths = Vec::new();
+        for m in &self.members {
+            info!("Building {}",m.build.name);
+            match m.build.build_in(whileyhome,&mut lock)? {
+                Some(mut wp) => {
+                    // Drop the member's own zip file
+                    wp.remove(0);
+                    whileypaths.push(wp);
+                }
+                None => { break; }
+            }
+        }
+        // Record what was used
+        let mut coordinates = Vec::new();
+        for m in &self.members {
+            coordinates.extend(m.build.coordinates(whileyhome)?);
+        }
+        lock.prune(&deps,&coordinates);
+        lock.write(path)?;
+        // Done
+        if whileypaths.len() == self.members.len() {
+            Ok(Some(whileypaths))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Order members such that each comes after any members on which it
+/// depends via a path dependency.  Otherwise, members remain in the
+/// order they were given.
+fn order(members: Vec<Member>) -> Result<Vec<Member>,Box<dyn Error>> {
+    // Determine dependencies between members
+    let mut edges = Vec::new();
+    for m in &members {
+        let mut es = Vec::new();
+        for d in &m.build.path_dependencies {
+            if let Ok(dir) = m.dir.join(d.path()).canonicalize() {
+                if let Some(i) = members.iter().position(|n| n.dir == dir) {
+                    es.push(i);
+                }
+            }
+        }
+        edges.push(es);
+    }
+    // Depth-first traversal
+    let mut visited = vec![false;members.len()];
+    let mut active = vec![false;members.len()];
+    let mut order = Vec::new();
+    for i in 0..members.len() {
+        visit(i,&edges,&mut visited,&mut active,&mut order,&members)?;
+    }
+    // Rearrange accordingly
+    let mut members : Vec<Option<Member>> = members.into_iter().map(Some).collect();
+    Ok(order.into_iter().map(|i| members[i].take().unwrap()).collect())
+}
+
+fn visit(i: usize, edges: &[Vec<usize>], visited: &mut [bool], active: &mut [bool], order: &mut Vec<usize>, members: &[Member]) -> Result<(),Box<dyn Error>> {
+    if active[i] {
+        let message = format!("cyclic dependency involving member \"{}\"",members[i].build.name);
+        return Err(Box::new(WorkspaceError{message}));
+    } else if visited[i] {
+        return Ok(());
+    }
+    active[i] = true;
+    for &j in &edges[i] {
+        visit(j,edges,visited,active,order,members)?;
+    }
+    active[i] = false;
+    visited[i] = true;
+    order.push(i);
+    Ok(())
+}
+
+// ===================================================================
+// Workspace Error
+// ===================================================================
+
+#[derive(Clone)]
+pub struct WorkspaceError {
+    pub message: String
+}
+
+impl fmt::Display for WorkspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid workspace: {}",self.message)
+    }
+}
+
+impl fmt::Debug for WorkspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid workspace: {}",self.message)
+    }
+}
+
+impl Error for WorkspaceError {}